@@ -0,0 +1,600 @@
+use prc::{
+    ParamKind,
+    ParamStruct,
+    ParamList,
+    hash40::Hash40
+};
+
+use crate::{
+    error::{
+        type_name,
+        ResolveError,
+        ResolveErrorKind
+    },
+    hash::Labels,
+    key::{
+        PrcKey,
+        PrcKeyType
+    }
+};
+
+/// Reading and editing params by [`PrcKey`], for both whole param files and any param inside of them.
+///
+/// Keys can point at structs and lists as well as values. On a [`ParamKind`] the empty key points at
+/// the param itself, while a [`ParamStruct`] has no param for the empty key.
+pub trait ParamAccess {
+    /// The param at `key`
+    fn get(&self, key: &PrcKey) -> Result<&ParamKind, ResolveError> {
+        self.get_with(key, Labels::global())
+    }
+
+    /// Like [`get`](Self::get), with errors naming fields by `labels` instead of the global table
+    fn get_with(&self, key: &PrcKey, labels: &Labels) -> Result<&ParamKind, ResolveError>;
+
+    /// The param at `key`, mutably
+    fn get_mut(&mut self, key: &PrcKey) -> Result<&mut ParamKind, ResolveError> {
+        self.get_mut_with(key, Labels::global())
+    }
+
+    /// Like [`get_mut`](Self::get_mut), with errors naming fields by `labels` instead of the global table
+    fn get_mut_with(&mut self, key: &PrcKey, labels: &Labels) -> Result<&mut ParamKind, ResolveError>;
+
+    /// Replaces the param at `key`, which has to exist already, and returns the old one
+    fn set(&mut self, key: &PrcKey, value: ParamKind) -> Result<ParamKind, ResolveError> {
+        self.set_with(key, value, Labels::global())
+    }
+
+    /// Like [`set`](Self::set), with errors naming fields by `labels` instead of the global table
+    fn set_with(&mut self, key: &PrcKey, value: ParamKind, labels: &Labels) -> Result<ParamKind, ResolveError> {
+        self.get_mut_with(key, labels).map(|param| std::mem::replace(param, value))
+    }
+
+    /// Takes the param at `key` out of its struct or list. Later list entries move down by one.
+    fn remove(&mut self, key: &PrcKey) -> Result<ParamKind, ResolveError> {
+        self.remove_with(key, Labels::global())
+    }
+
+    /// Like [`remove`](Self::remove), with errors naming fields by `labels` instead of the global table
+    fn remove_with(&mut self, key: &PrcKey, labels: &Labels) -> Result<ParamKind, ResolveError>;
+
+    /// Adds a param at `key`, which must not exist yet. New struct fields go at the end of the struct,
+    /// and list indices can go anywhere from the start of the list up to its length. `[+]` adds to the
+    /// end of a list and `[-n]` goes in front of the entry it points at.
+    fn insert(&mut self, key: &PrcKey, value: ParamKind) -> Result<(), ResolveError> {
+        self.insert_with(key, value, Labels::global())
+    }
+
+    /// Like [`insert`](Self::insert), with errors naming fields by `labels` instead of the global table
+    fn insert_with(&mut self, key: &PrcKey, value: ParamKind, labels: &Labels) -> Result<(), ResolveError>;
+
+    /// Every concrete key that `key` covers, with `[*]` and ranges like `[3..10]` replaced by each
+    /// index of the list they are used on, and `[-n]` by the index it points at. Other keys come back
+    /// as they are if they resolve.
+    ///
+    /// Selectors like `[fighter_kind=fighter_kind_mario]` become the index of every struct in the list
+    /// with a matching field, and it is an error when nothing matches.
+    ///
+    /// Every expanded key has to resolve, so a field that is missing from one entry of a list is an error.
+    fn expand(&self, key: &PrcKey) -> Result<Vec<PrcKey>, ResolveError> {
+        self.expand_with(key, Labels::global())
+    }
+
+    /// Like [`expand`](Self::expand), with errors naming fields by `labels` instead of the global table
+    fn expand_with(&self, key: &PrcKey, labels: &Labels) -> Result<Vec<PrcKey>, ResolveError>;
+}
+
+#[derive(Clone, Copy)]
+enum Container<'a> {
+    Struct(&'a ParamStruct),
+    List(&'a ParamList)
+}
+
+enum ContainerMut<'a> {
+    Struct(&'a mut ParamStruct),
+    List(&'a mut ParamList)
+}
+
+fn error(key: &PrcKey, resolved: usize, kind: ResolveErrorKind, labels: &Labels) -> ResolveError {
+    ResolveError {
+        key: key.clone().with_labels_of(labels),
+        resolved,
+        kind,
+        suggestions: vec![]
+    }
+}
+
+// The error for a field that isn't in `s`, which suggests the fields of `s` that look like the name it was typed with
+fn missing_field(key: &PrcKey, resolved: usize, field: Hash40, s: &ParamStruct, labels: &Labels) -> ResolveError {
+    let error = error(key, resolved, ResolveErrorKind::MissingField(field), labels);
+    ResolveError {
+        suggestions: match error.key.typed_name(field) {
+            Some(name) => labels.closest(name, s.0.iter().map(|(hash, _)| *hash)),
+            None => vec![]
+        },
+        ..error
+    }
+}
+
+// The error for following the segment at `resolved` into a param that isn't the right kind of container
+fn wrong_container(key: &PrcKey, resolved: usize, param: &ParamKind, labels: &Labels) -> ResolveError {
+    let found = type_name(param);
+    match key.segments()[resolved] {
+        PrcKeyType::StructField(_) => error(key, resolved, ResolveErrorKind::NotAStruct(found), labels),
+        _ => error(key, resolved, ResolveErrorKind::NotAList(found), labels)
+    }
+}
+
+// The index that `[-n]` points at in a list of `len`
+fn from_end(key: &PrcKey, resolved: usize, from_end: usize, len: usize, labels: &Labels) -> Result<usize, ResolveError> {
+    match len.checked_sub(from_end) {
+        Some(index) if from_end > 0 => Ok(index),
+        _ => Err(error(key, resolved, ResolveErrorKind::FromEndOutOfBounds { from_end, len }, labels))
+    }
+}
+
+fn container(param: &ParamKind) -> Option<Container<'_>> {
+    match param {
+        ParamKind::Struct(s) => Some(Container::Struct(s)),
+        ParamKind::List(l) => Some(Container::List(l)),
+        _ => None
+    }
+}
+
+fn step<'a>(container: Container<'a>, key: &PrcKey, idx: usize, labels: &Labels) -> Result<&'a ParamKind, ResolveError> {
+    match (container, &key.segments()[idx]) {
+        (Container::Struct(s), &PrcKeyType::StructField(field)) => s.0
+            .iter()
+            .find(|(hash, _)| *hash == field)
+            .map(|(_, p)| p)
+            .ok_or_else(|| missing_field(key, idx, field, s, labels)),
+        (Container::List(l), &PrcKeyType::ListIndex(index)) => l.0
+            .get(index)
+            .ok_or_else(|| error(key, idx, ResolveErrorKind::IndexOutOfBounds { index, len: l.0.len() }, labels)),
+        (Container::List(l), &PrcKeyType::ListFromEnd(n)) => Ok(&l.0[from_end(key, idx, n, l.0.len(), labels)?]),
+        (Container::List(l), &PrcKeyType::ListAppend) => {
+            Err(error(key, idx, ResolveErrorKind::IndexOutOfBounds { index: l.0.len(), len: l.0.len() }, labels))
+        },
+        (Container::Struct(_), _) => Err(error(key, idx, ResolveErrorKind::NotAList("struct"), labels)),
+        (Container::List(_), &PrcKeyType::StructField(_)) => Err(error(key, idx, ResolveErrorKind::NotAStruct("list"), labels)),
+        (_, _) => Err(error(key, idx, ResolveErrorKind::NotConcrete, labels))
+    }
+}
+
+fn step_mut<'a>(container: ContainerMut<'a>, key: &PrcKey, idx: usize, labels: &Labels) -> Result<&'a mut ParamKind, ResolveError> {
+    match (container, &key.segments()[idx]) {
+        (ContainerMut::Struct(s), &PrcKeyType::StructField(field)) => {
+            match s.0.iter().position(|(hash, _)| *hash == field) {
+                Some(pos) => Ok(&mut s.0[pos].1),
+                None => Err(missing_field(key, idx, field, s, labels))
+            }
+        },
+        (ContainerMut::List(l), &PrcKeyType::ListIndex(index)) => {
+            let len = l.0.len();
+            l.0.get_mut(index).ok_or_else(|| error(key, idx, ResolveErrorKind::IndexOutOfBounds { index, len }, labels))
+        },
+        (ContainerMut::List(l), &PrcKeyType::ListFromEnd(n)) => {
+            let index = from_end(key, idx, n, l.0.len(), labels)?;
+            Ok(&mut l.0[index])
+        },
+        (ContainerMut::List(l), &PrcKeyType::ListAppend) => {
+            Err(error(key, idx, ResolveErrorKind::IndexOutOfBounds { index: l.0.len(), len: l.0.len() }, labels))
+        },
+        (ContainerMut::Struct(_), _) => Err(error(key, idx, ResolveErrorKind::NotAList("struct"), labels)),
+        (ContainerMut::List(_), &PrcKeyType::StructField(_)) => Err(error(key, idx, ResolveErrorKind::NotAStruct("list"), labels)),
+        (_, _) => Err(error(key, idx, ResolveErrorKind::NotConcrete, labels))
+    }
+}
+
+// Follows the first `end` segments of the key, starting inside of `container`. `end` must not be 0.
+fn descend<'a>(mut container: Container<'a>, key: &PrcKey, end: usize, labels: &Labels) -> Result<&'a ParamKind, ResolveError> {
+    let mut idx = 0;
+    loop {
+        let param = step(container, key, idx, labels)?;
+        idx += 1;
+        if idx == end {
+            return Ok(param);
+        }
+        container = self::container(param).ok_or_else(|| wrong_container(key, idx, param, labels))?;
+    }
+}
+
+fn descend_mut<'a>(mut container: ContainerMut<'a>, key: &PrcKey, end: usize, labels: &Labels) -> Result<&'a mut ParamKind, ResolveError> {
+    let mut idx = 0;
+    loop {
+        let param = step_mut(container, key, idx, labels)?;
+        idx += 1;
+        if idx == end {
+            return Ok(param);
+        }
+        container = match param {
+            ParamKind::Struct(s) => ContainerMut::Struct(s),
+            ParamKind::List(l) => ContainerMut::List(l),
+            param => return Err(wrong_container(key, idx, param, labels))
+        };
+    }
+}
+
+// The struct or list that the last segment of the key is in
+fn parent_mut<'a>(root: ContainerMut<'a>, key: &PrcKey, labels: &Labels) -> Result<ContainerMut<'a>, ResolveError> {
+    let end = key.len() - 1;
+    if end == 0 {
+        return Ok(root);
+    }
+    match descend_mut(root, key, end, labels)? {
+        ParamKind::Struct(s) => Ok(ContainerMut::Struct(s)),
+        ParamKind::List(l) => Ok(ContainerMut::List(l)),
+        param => Err(wrong_container(key, end, param, labels))
+    }
+}
+
+fn remove_from(root: ContainerMut<'_>, key: &PrcKey, labels: &Labels) -> Result<ParamKind, ResolveError> {
+    let last = match key.last() {
+        Some(last) => last,
+        None => return Err(error(key, 0, ResolveErrorKind::EmptyKey, labels))
+    };
+    let idx = key.len() - 1;
+    match (parent_mut(root, key, labels)?, last) {
+        (ContainerMut::Struct(s), &PrcKeyType::StructField(field)) => {
+            match s.0.iter().position(|(hash, _)| *hash == field) {
+                Some(pos) => Ok(s.0.remove(pos).1),
+                None => Err(missing_field(key, idx, field, s, labels))
+            }
+        },
+        (ContainerMut::List(l), &PrcKeyType::ListIndex(index)) if index < l.0.len() => Ok(l.0.remove(index)),
+        (ContainerMut::List(l), &PrcKeyType::ListFromEnd(n)) => {
+            let index = from_end(key, idx, n, l.0.len(), labels)?;
+            Ok(l.0.remove(index))
+        },
+        (ContainerMut::List(l), &PrcKeyType::ListIndex(index)) => {
+            Err(error(key, idx, ResolveErrorKind::IndexOutOfBounds { index, len: l.0.len() }, labels))
+        },
+        (ContainerMut::Struct(_), _) => Err(error(key, idx, ResolveErrorKind::NotAList("struct"), labels)),
+        (ContainerMut::List(_), &PrcKeyType::StructField(_)) => Err(error(key, idx, ResolveErrorKind::NotAStruct("list"), labels)),
+        (_, _) => Err(error(key, idx, ResolveErrorKind::NotConcrete, labels))
+    }
+}
+
+fn insert_into(root: ContainerMut<'_>, key: &PrcKey, value: ParamKind, labels: &Labels) -> Result<(), ResolveError> {
+    let last = match key.last() {
+        Some(last) => last,
+        None => return Err(error(key, 0, ResolveErrorKind::EmptyKey, labels))
+    };
+    let idx = key.len() - 1;
+    match (parent_mut(root, key, labels)?, last) {
+        (ContainerMut::Struct(s), &PrcKeyType::StructField(field)) => {
+            if s.0.iter().any(|(hash, _)| *hash == field) {
+                return Err(error(key, idx, ResolveErrorKind::AlreadyExists, labels));
+            }
+            s.0.push((field, value));
+            Ok(())
+        },
+        (ContainerMut::List(l), &PrcKeyType::ListIndex(index)) if index <= l.0.len() => {
+            l.0.insert(index, value);
+            Ok(())
+        },
+        (ContainerMut::List(l), &PrcKeyType::ListFromEnd(n)) => {
+            let index = from_end(key, idx, n, l.0.len(), labels)?;
+            l.0.insert(index, value);
+            Ok(())
+        },
+        (ContainerMut::List(l), &PrcKeyType::ListAppend) => {
+            l.0.push(value);
+            Ok(())
+        },
+        (ContainerMut::List(l), &PrcKeyType::ListIndex(index)) => {
+            Err(error(key, idx, ResolveErrorKind::IndexOutOfBounds { index, len: l.0.len() }, labels))
+        },
+        (ContainerMut::Struct(_), _) => Err(error(key, idx, ResolveErrorKind::NotAList("struct"), labels)),
+        (ContainerMut::List(_), &PrcKeyType::StructField(_)) => Err(error(key, idx, ResolveErrorKind::NotAStruct("list"), labels)),
+        (_, _) => Err(error(key, idx, ResolveErrorKind::NotConcrete, labels))
+    }
+}
+
+// Expands the segments of `key` from `idx` onwards inside of `container`. `path` holds the concrete
+// segments that lead to `container`, and every finished key is pushed to `out`.
+fn expand_from(container: Container<'_>, key: &PrcKey, idx: usize, path: &mut PrcKey, out: &mut Vec<PrcKey>, labels: &Labels) -> Result<(), ResolveError> {
+    // Errors are reported against the expanded key so far followed by the rest of the pattern
+    let rest = &key.segments()[idx + 1..];
+    let with_rest = |path: &PrcKey| -> PrcKey { path.iter().chain(rest.iter()).cloned().collect::<PrcKey>().with_names_of(key) };
+    let pattern_key = || -> PrcKey { path.iter().chain(&key.segments()[idx..]).cloned().collect::<PrcKey>().with_names_of(key) };
+
+    let segments: Vec<PrcKeyType> = match (container, &key.segments()[idx]) {
+        (Container::List(l), &PrcKeyType::ListAll) => (0..l.0.len()).map(PrcKeyType::ListIndex).collect(),
+        (Container::List(l), &PrcKeyType::ListRange { start, end }) => {
+            let len = l.0.len();
+            let out_of_bounds = match end {
+                Some(end) if end > len => Some(end - 1),
+                None if start > len => Some(start),
+                _ => None
+            };
+            if let Some(index) = out_of_bounds {
+                return Err(error(&pattern_key(), idx, ResolveErrorKind::IndexOutOfBounds { index, len }, labels));
+            }
+            (start..end.unwrap_or(len)).map(PrcKeyType::ListIndex).collect()
+        },
+        (Container::List(l), &PrcKeyType::ListFromEnd(n)) => vec![PrcKeyType::ListIndex(from_end(&pattern_key(), idx, n, l.0.len(), labels)?)],
+        (Container::List(l), PrcKeyType::ListSelect { field, value }) => {
+            let selected: Vec<_> = l.0
+                .iter()
+                .enumerate()
+                .filter(|(_, param)| match param {
+                    ParamKind::Struct(s) => s.0.iter().any(|(hash, param)| hash == field && value.matches(param)),
+                    _ => false
+                })
+                .map(|(index, _)| PrcKeyType::ListIndex(index))
+                .collect();
+            if selected.is_empty() {
+                return Err(error(&pattern_key(), idx, ResolveErrorKind::NoMatch, labels));
+            }
+            selected
+        },
+        (Container::Struct(_), _) if !key.segments()[idx].is_concrete() => {
+            return Err(error(&pattern_key(), idx, ResolveErrorKind::NotAList("struct"), labels));
+        },
+        (_, segment) => vec![segment.clone()]
+    };
+
+    for segment in segments {
+        path.push(segment);
+        let param = step(container, path, idx, labels).map_err(|e| ResolveError { key: with_rest(&e.key).with_labels_of(labels), ..e })?;
+        if rest.is_empty() {
+            out.push(path.clone());
+        } else {
+            let full = with_rest(path);
+            let next = self::container(param).ok_or_else(|| wrong_container(&full, idx + 1, param, labels))?;
+            expand_from(next, key, idx + 1, path, out, labels)?;
+        }
+        path.pop();
+    }
+    Ok(())
+}
+
+impl ParamAccess for ParamStruct {
+    fn get_with(&self, key: &PrcKey, labels: &Labels) -> Result<&ParamKind, ResolveError> {
+        if key.is_empty() {
+            return Err(error(key, 0, ResolveErrorKind::EmptyKey, labels));
+        }
+        descend(Container::Struct(self), key, key.len(), labels)
+    }
+
+    fn get_mut_with(&mut self, key: &PrcKey, labels: &Labels) -> Result<&mut ParamKind, ResolveError> {
+        if key.is_empty() {
+            return Err(error(key, 0, ResolveErrorKind::EmptyKey, labels));
+        }
+        descend_mut(ContainerMut::Struct(self), key, key.len(), labels)
+    }
+
+    fn remove_with(&mut self, key: &PrcKey, labels: &Labels) -> Result<ParamKind, ResolveError> {
+        remove_from(ContainerMut::Struct(self), key, labels)
+    }
+
+    fn insert_with(&mut self, key: &PrcKey, value: ParamKind, labels: &Labels) -> Result<(), ResolveError> {
+        insert_into(ContainerMut::Struct(self), key, value, labels)
+    }
+
+    fn expand_with(&self, key: &PrcKey, labels: &Labels) -> Result<Vec<PrcKey>, ResolveError> {
+        if key.is_empty() {
+            return Err(error(key, 0, ResolveErrorKind::EmptyKey, labels));
+        }
+        let mut keys = vec![];
+        expand_from(Container::Struct(self), key, 0, &mut PrcKey::new().with_names_of(key), &mut keys, labels)?;
+        Ok(keys)
+    }
+}
+
+impl ParamAccess for ParamKind {
+    fn get_with(&self, key: &PrcKey, labels: &Labels) -> Result<&ParamKind, ResolveError> {
+        if key.is_empty() {
+            return Ok(self);
+        }
+        let root = container(self).ok_or_else(|| wrong_container(key, 0, self, labels))?;
+        descend(root, key, key.len(), labels)
+    }
+
+    fn get_mut_with(&mut self, key: &PrcKey, labels: &Labels) -> Result<&mut ParamKind, ResolveError> {
+        if key.is_empty() {
+            return Ok(self);
+        }
+        match self {
+            ParamKind::Struct(s) => descend_mut(ContainerMut::Struct(s), key, key.len(), labels),
+            ParamKind::List(l) => descend_mut(ContainerMut::List(l), key, key.len(), labels),
+            param => Err(wrong_container(key, 0, param, labels))
+        }
+    }
+
+    fn remove_with(&mut self, key: &PrcKey, labels: &Labels) -> Result<ParamKind, ResolveError> {
+        match self {
+            ParamKind::Struct(s) => remove_from(ContainerMut::Struct(s), key, labels),
+            ParamKind::List(l) => remove_from(ContainerMut::List(l), key, labels),
+            _ if key.is_empty() => Err(error(key, 0, ResolveErrorKind::EmptyKey, labels)),
+            param => Err(wrong_container(key, 0, param, labels))
+        }
+    }
+
+    fn insert_with(&mut self, key: &PrcKey, value: ParamKind, labels: &Labels) -> Result<(), ResolveError> {
+        match self {
+            ParamKind::Struct(s) => insert_into(ContainerMut::Struct(s), key, value, labels),
+            ParamKind::List(l) => insert_into(ContainerMut::List(l), key, value, labels),
+            _ if key.is_empty() => Err(error(key, 0, ResolveErrorKind::EmptyKey, labels)),
+            param => Err(wrong_container(key, 0, param, labels))
+        }
+    }
+
+    fn expand_with(&self, key: &PrcKey, labels: &Labels) -> Result<Vec<PrcKey>, ResolveError> {
+        if key.is_empty() {
+            return Ok(vec![PrcKey::new()]);
+        }
+        let root = container(self).ok_or_else(|| wrong_container(key, 0, self, labels))?;
+        let mut keys = vec![];
+        expand_from(root, key, 0, &mut PrcKey::new().with_names_of(key), &mut keys, labels)?;
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+fn key(s: &str) -> PrcKey {
+    s.parse().unwrap()
+}
+
+#[test]
+fn get_set_test() {
+    use prc::hash40::to_hash40;
+
+    let mut params = ParamStruct(vec![
+        (to_hash40("access_table"), ParamKind::List(ParamList(vec![
+            ParamKind::Struct(ParamStruct(vec![(to_hash40("access_float"), ParamKind::Float(1.0))])),
+        ]))),
+    ]);
+
+    assert_eq!(params.get(&key("access_table[0].access_float")), Ok(&ParamKind::Float(1.0)));
+    assert!(matches!(params.get(&key("access_table[0]")), Ok(ParamKind::Struct(_))));
+    assert_eq!(params.set(&key("access_table[0].access_float"), ParamKind::Float(2.0)), Ok(ParamKind::Float(1.0)));
+
+    let table = params.get(&key("access_table")).unwrap();
+    assert_eq!(table.get(&key("[0].access_float")), Ok(&ParamKind::Float(2.0)));
+    assert_eq!(table.get(&PrcKey::new()), Ok(table));
+
+    let err = params.get(&key("access_table[0].access_missing.access_float")).unwrap_err();
+    assert_eq!(err.kind, ResolveErrorKind::MissingField(to_hash40("access_missing")));
+    assert_eq!(err.resolved_prefix(), key("access_table[0]"));
+
+    let err = params.get(&key("access_table[3].access_float")).unwrap_err();
+    assert_eq!(err.kind, ResolveErrorKind::IndexOutOfBounds { index: 3, len: 1 });
+
+    let err = params.get(&key("access_table[0].access_float[0]")).unwrap_err();
+    assert_eq!(err.kind, ResolveErrorKind::NotAList("float"));
+    assert_eq!(err.resolved, 3);
+}
+
+#[test]
+fn insert_remove_test() {
+    use prc::hash40::to_hash40;
+
+    let mut params = ParamStruct(vec![
+        (to_hash40("access_table"), ParamKind::List(ParamList(vec![ParamKind::U8(0), ParamKind::U8(2)]))),
+    ]);
+
+    params.insert(&key("access_table[1]"), ParamKind::U8(1)).unwrap();
+    params.insert(&key("access_table[3]"), ParamKind::U8(3)).unwrap();
+    params.insert(&key("access_flag"), ParamKind::Bool(true)).unwrap();
+    assert_eq!(params.insert(&key("access_flag"), ParamKind::Bool(false)).unwrap_err().kind, ResolveErrorKind::AlreadyExists);
+    assert_eq!(params.insert(&key("access_table[5]"), ParamKind::U8(5)).unwrap_err().kind, ResolveErrorKind::IndexOutOfBounds { index: 5, len: 4 });
+    assert_eq!(params.get(&key("access_table")), Ok(&ParamKind::List(ParamList((0..4).map(ParamKind::U8).collect()))));
+
+    assert_eq!(params.remove(&key("access_table[0]")), Ok(ParamKind::U8(0)));
+    assert_eq!(params.remove(&key("access_flag")), Ok(ParamKind::Bool(true)));
+    assert_eq!(params.get(&key("access_table[0]")), Ok(&ParamKind::U8(1)));
+    assert!(params.get(&key("access_flag")).is_err());
+    assert_eq!(params.remove(&PrcKey::new()).unwrap_err().kind, ResolveErrorKind::EmptyKey);
+}
+
+#[test]
+fn expand_test() {
+    use prc::hash40::to_hash40;
+
+    let entry = |x: f32| ParamKind::Struct(ParamStruct(vec![(to_hash40("access_float"), ParamKind::Float(x))]));
+    let params = ParamStruct(vec![
+        (to_hash40("access_table"), ParamKind::List(ParamList(vec![entry(0.0), entry(1.0), entry(2.0), ParamKind::Struct(ParamStruct(vec![]))]))),
+    ]);
+
+    let keys = params.expand(&key("access_table[0..3].access_float")).unwrap();
+    assert_eq!(keys, vec![key("access_table[0].access_float"), key("access_table[1].access_float"), key("access_table[2].access_float")]);
+    assert_eq!(params.expand(&key("access_table[1..]")).unwrap().len(), 3);
+    assert_eq!(params.expand(&key("access_table[4..]")).unwrap(), vec![]);
+    assert_eq!(params.expand(&key("access_table[2]")).unwrap(), vec![key("access_table[2]")]);
+
+    let err = params.expand(&key("access_table[*].access_float")).unwrap_err();
+    assert_eq!(err.key, key("access_table[3].access_float"));
+    assert_eq!(err.kind, ResolveErrorKind::MissingField(to_hash40("access_float")));
+
+    let err = params.expand(&key("access_table[2..6]")).unwrap_err();
+    assert_eq!(err.kind, ResolveErrorKind::IndexOutOfBounds { index: 5, len: 4 });
+    assert_eq!(params.get(&key("access_table[*]")).unwrap_err().kind, ResolveErrorKind::NotConcrete);
+}
+
+#[test]
+fn expand_select_test() {
+    use prc::hash40::to_hash40;
+
+    let entry = |kind: &str, id: u8| ParamKind::Struct(ParamStruct(vec![
+        (to_hash40("access_kind"), ParamKind::Hash(to_hash40(kind))),
+        (to_hash40("access_id"), ParamKind::U8(id)),
+    ]));
+    let params = ParamStruct(vec![
+        (to_hash40("access_table"), ParamKind::List(ParamList(vec![entry("access_a", 0), ParamKind::U8(1), entry("access_b", 2), entry("access_a", 3)]))),
+    ]);
+
+    assert_eq!(params.expand(&key("access_table[access_kind=access_a].access_id")).unwrap(), vec![key("access_table[0].access_id"), key("access_table[3].access_id")]);
+    assert_eq!(params.expand(&key("access_table[access_id=2]")).unwrap(), vec![key("access_table[2]")]);
+    assert_eq!(params.expand(&key("access_table[access_kind=access_c]")).unwrap_err().kind, ResolveErrorKind::NoMatch);
+    assert_eq!(params.expand(&key("access_table[access_id=\"2\"]")).unwrap_err().kind, ResolveErrorKind::NoMatch);
+}
+
+#[test]
+fn missing_field_suggestion_test() {
+    use prc::hash40::to_hash40;
+
+    crate::hash::add_hashes(vec!["access_walk_speed_max", "access_run_speed_max"]);
+    let mut params = ParamStruct(vec![
+        (to_hash40("access_walk_speed_max"), ParamKind::Float(1.0)),
+        (to_hash40("access_run_speed_max"), ParamKind::Float(2.0)),
+    ]);
+
+    let err = params.get(&key("access_walk_sped_max")).unwrap_err();
+    assert_eq!(err.suggestions, vec!["access_walk_speed_max".to_string(), "access_run_speed_max".to_string()]);
+    assert!(err.to_string().ends_with("there is no field `access_walk_sped_max`, did you mean `access_walk_speed_max` or `access_run_speed_max`?"), "{}", err);
+    assert_eq!(params.remove(&key("access_run_sped_max")).unwrap_err().suggestions, vec!["access_run_speed_max".to_string()]);
+    assert!(params.get_mut(&key("access_jump_count")).unwrap_err().suggestions.is_empty());
+
+    // Keys made out of other ones still know what their fields were typed as
+    let err = params.expand(&key("access_walk_sped_max")).unwrap_err();
+    assert_eq!(err.reason(), "there is no field `access_walk_sped_max`");
+    assert_eq!(err.suggestions.len(), 2);
+    let parent = key("access_table.access_run_sped_max.access_field").parent().unwrap();
+    assert_eq!(parent.typed_name(to_hash40("access_run_sped_max")), Some("access_run_sped_max"));
+}
+
+#[test]
+fn end_relative_access_test() {
+    use prc::hash40::to_hash40;
+
+    let mut params = ParamStruct(vec![
+        (to_hash40("access_table"), ParamKind::List(ParamList(vec![ParamKind::U8(0), ParamKind::U8(1)]))),
+    ]);
+
+    assert_eq!(params.get(&key("access_table[-1]")), Ok(&ParamKind::U8(1)));
+    assert_eq!(params.get(&key("access_table[-3]")).unwrap_err().kind, ResolveErrorKind::FromEndOutOfBounds { from_end: 3, len: 2 });
+    assert_eq!(params.expand(&key("access_table[-2]")).unwrap(), vec![key("access_table[0]")]);
+
+    params.insert(&key("access_table[+]"), ParamKind::U8(3)).unwrap();
+    params.insert(&key("access_table[-1]"), ParamKind::U8(2)).unwrap();
+    assert_eq!(params.get(&key("access_table")), Ok(&ParamKind::List(ParamList((0..4).map(ParamKind::U8).collect()))));
+    assert_eq!(params.remove(&key("access_table[-1]")), Ok(ParamKind::U8(3)));
+    assert!(params.get(&key("access_table[+]")).is_err());
+}
+
+#[test]
+fn resolve_labels_test() {
+    use prc::hash40::to_hash40;
+
+    let labels = Labels::new();
+    labels.add_all(vec!["access_local_table", "access_local_speed"]);
+    let mut params = ParamStruct(vec![
+        (to_hash40("access_local_table"), ParamKind::List(ParamList(vec![
+            ParamKind::Struct(ParamStruct(vec![(to_hash40("access_local_speed"), ParamKind::Float(1.0))])),
+        ]))),
+    ]);
+
+    // Only the local table knows these labels, so they have to come from it
+    let hashed = PrcKey::builder().hash(to_hash40("access_local_table")).index(0).hash(to_hash40("access_local_sped")).build();
+    let err = params.get_with(&hashed, &labels).unwrap_err();
+    assert_eq!(err.to_string(), format!("`access_local_table[0].{:#x}` could not be resolved at `access_local_table[0].{0:#x}`: there is no field `{0:#x}`", to_hash40("access_local_sped").0));
+    assert!(params.get(&hashed).unwrap_err().to_string().starts_with(&format!("`{:#x}[0]", to_hash40("access_local_table").0)));
+
+    let err = params.expand_with(&key("access_local_table[*].access_local_sped"), &labels).unwrap_err();
+    assert_eq!(err.suggestions, vec!["access_local_speed".to_string()]);
+    assert!(err.to_string().starts_with("`access_local_table[0].access_local_sped` could not be resolved"), "{}", err);
+    assert_eq!(params.remove_with(&key("access_local_table[0].access_local_sped"), &labels).unwrap_err().suggestions, vec!["access_local_speed".to_string()]);
+    assert!(params.get(&key("access_local_table[0].access_local_sped")).unwrap_err().suggestions.is_empty());
+    assert_eq!(ResolveErrorKind::MissingField(Hash40(0x10)).to_string(), "there is no field `0x10`");
+}
@@ -0,0 +1,348 @@
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader},
+    fs::File,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering}
+    },
+    thread
+};
+
+use prc::{
+    ParamKind,
+    ParamStruct,
+    hash40::Hash40
+};
+
+use crate::{
+    error::Result,
+    hash::Labels
+};
+
+#[cfg(test)]
+use prc::hash40::to_hash40;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+}
+
+// Feeds more bytes into a running crc32, so that candidates sharing a start only hash it once.
+// The state isn't inverted yet, the crc of everything fed so far is `!state`.
+fn crc_update(mut state: u32, bytes: &[u8]) -> u32 {
+    for byte in bytes {
+        state = CRC_TABLE[((state ^ *byte as u32) & 0xFF) as usize] ^ (state >> 8);
+    }
+    state
+}
+
+/// Recovers labels for unlabeled hashes by trying candidate strings against them.
+///
+/// Candidates are a prefix, one or more words joined by the separator, then a suffix, such as
+/// `fighter_` + `jump_speed` + `_max`. A hash40 keeps the length of its label in its top byte,
+/// so only candidates of a length that one of the hashes has are ever hashed.
+#[derive(Debug, Clone)]
+pub struct Cracker {
+    words: Vec<String>,
+    // Labels that are split into words when cracking, once the separator is known
+    token_labels: Vec<String>,
+    prefixes: Vec<String>,
+    suffixes: Vec<String>,
+    separator: String,
+    max_words: usize,
+    threads: usize
+}
+
+impl Default for Cracker {
+    fn default() -> Self {
+        Self {
+            words: vec![],
+            token_labels: vec![],
+            prefixes: vec![String::new()],
+            suffixes: vec![String::new()],
+            separator: "_".to_string(),
+            max_words: 3,
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get())
+        }
+    }
+}
+
+impl Cracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds words that candidates are built from
+    pub fn words<I: IntoIterator<Item = S>, S: AsRef<str>>(mut self, words: I) -> Self {
+        self.words.extend(words.into_iter().map(|word| word.as_ref().to_string()));
+        self
+    }
+
+    /// Adds every part of the labels in `labels` between separators as a word, so `walk_speed_max`
+    /// gives `walk`, `speed` and `max`. The labels are split with the separator that is set when
+    /// [`crack`](Self::crack) is called.
+    pub fn tokens_from(mut self, labels: &Labels) -> Self {
+        self.token_labels.extend(labels.entries().into_iter().map(|(_, label)| label));
+        self
+    }
+
+    /// Adds the words of a wordlist file, one per line
+    pub fn load_words<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                self.words.push(line.trim().to_string());
+            }
+        }
+        Ok(self)
+    }
+
+    /// Adds strings that candidates may start with. Candidates without a prefix are always tried too.
+    pub fn prefixes<I: IntoIterator<Item = S>, S: AsRef<str>>(mut self, prefixes: I) -> Self {
+        self.prefixes.extend(prefixes.into_iter().map(|prefix| prefix.as_ref().to_string()));
+        self
+    }
+
+    /// Adds strings that candidates may end with. Candidates without a suffix are always tried too.
+    pub fn suffixes<I: IntoIterator<Item = S>, S: AsRef<str>>(mut self, suffixes: I) -> Self {
+        self.suffixes.extend(suffixes.into_iter().map(|suffix| suffix.as_ref().to_string()));
+        self
+    }
+
+    /// What goes between words, `_` by default
+    pub fn separator<S: Into<String>>(mut self, separator: S) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// The most words in one candidate, 3 by default
+    pub fn max_words(mut self, max_words: usize) -> Self {
+        self.max_words = max_words;
+        self
+    }
+
+    /// How many threads to try candidates on, every available core by default
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Tries every candidate against `targets` and returns the labels that match, sorted by hash.
+    /// A hash can show up more than once if several candidates collide on it.
+    pub fn crack<I: IntoIterator<Item = Hash40>>(&self, targets: I) -> Vec<(Hash40, String)> {
+        let targets: HashSet<u64> = targets.into_iter().map(|hash| hash.0).collect();
+        let tokens = self
+            .token_labels
+            .iter()
+            .flat_map(|label| label.split(self.separator.as_str()))
+            .filter(|token| !token.is_empty());
+        let mut search = Search {
+            targets,
+            lengths: [false; 256],
+            max_len: 0,
+            words: sorted(self.words.iter().map(String::as_str).chain(tokens)),
+            prefixes: sorted(self.prefixes.iter().map(String::as_str)),
+            suffixes: sorted(self.suffixes.iter().map(String::as_str)),
+            separator: self.separator.as_bytes().to_vec(),
+            max_words: self.max_words
+        };
+        for hash in search.targets.iter() {
+            let len = (hash >> 32) as usize;
+            if len < 256 {
+                search.lengths[len] = true;
+                search.max_len = search.max_len.max(len);
+            }
+        }
+        if search.words.is_empty() || search.max_words == 0 || search.max_len == 0 {
+            return vec![];
+        }
+
+        // Each job is a prefix and a first word, handed out to whichever thread is free next
+        let jobs = search.prefixes.len() * search.words.len();
+        let search = Arc::new(search);
+        let next = Arc::new(AtomicUsize::new(0));
+        let workers: Vec<_> = (0..self.threads.min(jobs))
+            .map(|_| {
+                let search = Arc::clone(&search);
+                let next = Arc::clone(&next);
+                thread::spawn(move || {
+                    let mut found = vec![];
+                    let mut candidate = vec![];
+                    loop {
+                        let job = next.fetch_add(1, Ordering::Relaxed);
+                        if job >= jobs {
+                            break found;
+                        }
+                        let prefix = &search.prefixes[job / search.words.len()];
+                        let word = &search.words[job % search.words.len()];
+                        if prefix.len() + word.len() + search.min_suffix() > search.max_len {
+                            continue;
+                        }
+                        candidate.clear();
+                        candidate.extend_from_slice(prefix);
+                        candidate.extend_from_slice(word);
+                        let state = crc_update(crc_update(!0, prefix), word);
+                        search.extend(&mut candidate, state, 1, &mut found);
+                    }
+                })
+            })
+            .collect();
+        let mut found: Vec<(Hash40, String)> = workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect();
+        found.sort();
+        found.dedup();
+        found
+    }
+}
+
+fn sorted<'a, I: Iterator<Item = &'a str>>(strings: I) -> Vec<Vec<u8>> {
+    let mut sorted: Vec<Vec<u8>> = strings.map(|s| s.as_bytes().to_vec()).collect();
+    sorted.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+    sorted.dedup();
+    sorted
+}
+
+struct Search {
+    targets: HashSet<u64>,
+    lengths: [bool; 256],
+    max_len: usize,
+    words: Vec<Vec<u8>>,
+    prefixes: Vec<Vec<u8>>,
+    suffixes: Vec<Vec<u8>>,
+    separator: Vec<u8>,
+    max_words: usize
+}
+
+impl Search {
+    fn min_suffix(&self) -> usize {
+        self.suffixes.first().map_or(0, |suffix| suffix.len())
+    }
+
+    // Tries `candidate` with every suffix, then every candidate with more words after it
+    fn extend(&self, candidate: &mut Vec<u8>, state: u32, words: usize, found: &mut Vec<(Hash40, String)>) {
+        for suffix in self.suffixes.iter() {
+            let len = candidate.len() + suffix.len();
+            if len > self.max_len {
+                break;
+            }
+            if self.lengths[len] {
+                let hash = (!crc_update(state, suffix)) as u64 | ((len as u64) << 32);
+                if self.targets.contains(&hash) {
+                    let mut label = candidate.clone();
+                    label.extend_from_slice(suffix);
+                    found.push((Hash40(hash), String::from_utf8_lossy(&label).into_owned()));
+                }
+            }
+        }
+
+        if words == self.max_words {
+            return;
+        }
+        let state = crc_update(state, &self.separator);
+        let start = candidate.len();
+        for word in self.words.iter() {
+            if start + self.separator.len() + word.len() + self.min_suffix() > self.max_len {
+                break;
+            }
+            candidate.extend_from_slice(&self.separator);
+            candidate.extend_from_slice(word);
+            self.extend(candidate, crc_update(state, word), words + 1, found);
+            candidate.truncate(start);
+        }
+    }
+}
+
+/// Every hash in `params` that `labels` has no label for, both field names and hash values, sorted
+pub fn unknown_hashes(params: &ParamStruct, labels: &Labels) -> Vec<Hash40> {
+    fn walk_struct(params: &ParamStruct, labels: &Labels, found: &mut Vec<Hash40>) {
+        for (hash, param) in params.0.iter() {
+            if !labels.contains(*hash) {
+                found.push(*hash);
+            }
+            walk(param, labels, found);
+        }
+    }
+
+    fn walk(param: &ParamKind, labels: &Labels, found: &mut Vec<Hash40>) {
+        match param {
+            ParamKind::Struct(s) => walk_struct(s, labels, found),
+            ParamKind::List(l) => l.0.iter().for_each(|param| walk(param, labels, found)),
+            ParamKind::Hash(hash) if !labels.contains(*hash) => found.push(*hash),
+            _ => {}
+        }
+    }
+
+    let mut found = vec![];
+    walk_struct(params, labels, &mut found);
+    found.sort();
+    found.dedup();
+    found
+}
+
+#[test]
+fn crc_update_test() {
+    for label in ["", "a", "walk_speed_max", "fighter_kind_mario"].iter() {
+        let hash = (!crc_update(!0, label.as_bytes())) as u64 | ((label.len() as u64) << 32);
+        assert_eq!(Hash40(hash), to_hash40(label));
+    }
+}
+
+#[test]
+fn crack_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["crack_walk_speed", "crack_run_accel"]);
+    let targets = vec![
+        to_hash40("crack_run_speed_max"),
+        to_hash40("crack_walk_accel"),
+        to_hash40("fighter_crack_jump"),
+        to_hash40("crack_not_in_any_list")
+    ];
+    let cracker = Cracker::new()
+        .tokens_from(&labels)
+        .words(vec!["jump"])
+        .prefixes(vec!["fighter_"])
+        .suffixes(vec!["_max"])
+        .threads(4);
+
+    let mut expected = vec![
+        (to_hash40("crack_run_speed_max"), "crack_run_speed_max".to_string()),
+        (to_hash40("crack_walk_accel"), "crack_walk_accel".to_string()),
+        (to_hash40("fighter_crack_jump"), "fighter_crack_jump".to_string())
+    ];
+    expected.sort();
+    assert_eq!(cracker.crack(targets.clone()), expected);
+    assert_eq!(cracker.max_words(2).crack(targets), vec![(to_hash40("fighter_crack_jump"), "fighter_crack_jump".to_string())]);
+
+    // The separator is picked up even when it is set after the labels were added
+    let labels = Labels::new();
+    labels.add("crack-dash-speed");
+    let cracker = Cracker::new().tokens_from(&labels).separator("-").threads(1);
+    assert_eq!(cracker.crack(vec![to_hash40("speed-dash")]), vec![(to_hash40("speed-dash"), "speed-dash".to_string())]);
+}
+
+#[test]
+fn unknown_hashes_test() {
+    let labels = Labels::new();
+    labels.add("unknown_known");
+    let params = ParamStruct(vec![
+        (to_hash40("unknown_known"), ParamKind::Hash(to_hash40("unknown_value"))),
+        (to_hash40("unknown_field"), ParamKind::Hash(to_hash40("unknown_known")))
+    ]);
+    let mut expected = vec![to_hash40("unknown_value"), to_hash40("unknown_field")];
+    expected.sort();
+    assert_eq!(unknown_hashes(&params, &labels), expected);
+}
@@ -1,10 +1,15 @@
-use std::path::Path;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{Read, Write},
+    path::Path
+};
 
 use prc::{
     ParamKind,
     ParamStruct,
     ParamList,
-    hash40::{to_hash40, Hash40}
+    hash40::{self, Hash40}
 };
 
 use serde::{
@@ -13,106 +18,141 @@ use serde::{
 };
 
 use crate::{
+    access::ParamAccess,
+    dump,
+    error::{type_name, Error, ResolveError, Result},
+    hash::{LabelSource, Labels},
+    format::{
+        self,
+        Format,
+        MAGIC,
+        BINARY_VERSION,
+        LABELED_BINARY_VERSION
+    },
     key::{
+        split_assignment,
         PrcKey,
-        PrcKeyType
+        PrcKeyType,
+        SelectorValue
     }
 };
 
-#[derive(Serialize, Deserialize)]
+#[cfg(test)]
+use crate::prc_key;
+#[cfg(test)]
+use prc::hash40::to_hash40;
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Diff(pub Vec<(PrcKey, ParamKind)>);
 
-impl Diff {
-    fn find_key_in_list<'a>(key: &PrcKey, params: &'a mut ParamList) -> Option<&'a mut ParamKind> {
-        let idx = match &key.ty {
-            PrcKeyType::StructField(_) => return None,
-            PrcKeyType::ListIndex(idx) => *idx
-        };
+// The layout of patches with embedded labels, in binary and JSON
+#[derive(Serialize)]
+struct LabeledRef<'a> {
+    labels: BTreeMap<Hash40, String>,
+    patch: &'a Diff
+}
 
-        params.0
-            .get_mut(idx)
-            .map(|x| {
-                if key.next.is_none() {
-                    match x {
-                        ParamKind::Struct(_) => None,
-                        ParamKind::List(_) => None,
-                        x => Some(x)
-                    }
-                } else {
-                    match x {
-                        ParamKind::Struct(s) if key.next.is_some() => {
-                            Self::find_key_in_struct(key.next.as_ref().unwrap().as_ref(), s)
-                        },
-                        ParamKind::List(l) if key.next.is_some() => {
-                            Self::find_key_in_list(key.next.as_ref().unwrap().as_ref(), l)
-                        },
-                        _ => None
-                    }
-                }
-            })
-            .flatten()
+#[derive(Deserialize)]
+struct Labeled {
+    labels: BTreeMap<Hash40, String>,
+    patch: Diff
+}
+
+/// What [`Diff::apply`] did with a patch
+#[derive(Debug, Default)]
+#[must_use]
+pub struct ApplyReport {
+    /// How many params were changed, counting every param that a list pattern covers
+    pub applied: usize,
+    /// Why each entry that was left out didn't fit
+    pub skipped: Vec<Error>
+}
+
+impl ApplyReport {
+    /// Whether every entry of the patch was applied
+    pub fn is_complete(&self) -> bool {
+        self.skipped.is_empty()
     }
+}
 
-    fn find_key_in_struct<'a>(key: &PrcKey, params: &'a mut ParamStruct) -> Option<&'a mut ParamKind> {
-        let field = match &key.ty {
-            PrcKeyType::StructField(hash) => *hash,
-            PrcKeyType::ListIndex(_) => return None
-        };
+impl fmt::Display for ApplyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "applied {} params, skipped {} entries", self.applied, self.skipped.len())?;
+        for error in self.skipped.iter() {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
 
-        let mut param = None;
-        for (hash, p) in params.0.iter_mut() {
-            if *hash == field {
-                param = Some(p);
-                break;
-            }
+/// What [`Diff::generate`] found
+#[derive(Debug)]
+#[must_use]
+pub struct GenerateReport {
+    /// Every value that changed
+    pub diff: Diff,
+    /// Why each change that the patch can't hold was left out of it
+    pub skipped: Vec<Error>
+}
+
+impl GenerateReport {
+    /// Whether the patch holds every change
+    pub fn is_complete(&self) -> bool {
+        self.skipped.is_empty()
+    }
+}
+
+impl fmt::Display for GenerateReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "found {} changed params, skipped {} changes", self.diff.0.len(), self.skipped.len())?;
+        for error in self.skipped.iter() {
+            write!(f, "\n  {}", error)?;
         }
+        Ok(())
+    }
+}
 
-        param
-            .map(|x| {
-                if key.next.is_none() {
-                    match x {
-                        ParamKind::Struct(_) => None,
-                        ParamKind::List(_) => None,
-                        x => Some(x)
-                    }
-                } else {
-                    match x {
-                        ParamKind::Struct(s) if key.next.is_some() => {
-                            Self::find_key_in_struct(key.next.as_ref().unwrap().as_ref(), s)
-                        },
-                        ParamKind::List(l) if key.next.is_some() => {
-                            Self::find_key_in_list(key.next.as_ref().unwrap().as_ref(), l)
-                        },
-                        _ => None
-                    }
-                }
-            })
-            .flatten()
+// A plain decimal or `0x` hex integer of one type
+fn untyped_int<T: std::str::FromStr>(s: &str, from_str_radix: fn(&str, u32) -> std::result::Result<T, std::num::ParseIntError>) -> Option<T> {
+    match s.strip_prefix("0x") {
+        Some(hex) => from_str_radix(hex, 16).ok(),
+        None => s.parse().ok()
     }
+}
+
+impl Diff {
+    fn get_param_kind_from_str(s: &str, labels: &Labels) -> Option<ParamKind> {
+        // Typed values like the ones in a full dump are taken as-is
+        if let Some(param) = dump::parse_value(s, labels) {
+            return Some(param);
+        } else if s.starts_with("hash40(") {
+            return None;
+        }
 
-    fn get_param_kind_from_str(s: &str) -> Option<ParamKind> {
-        if s.starts_with("\"") && s.ends_with("\"") {
+        if s.len() >= 2 && s.starts_with("\"") && s.ends_with("\"") {
             Some(ParamKind::Str(s.trim_start_matches("\"").trim_end_matches("\"").to_string()))
+        } else if s.starts_with("\"") {
+            None
         } else if s == "true" {
             Some(ParamKind::Bool(true))
         } else if s == "false" {
             Some(ParamKind::Bool(false))
         } else if s.starts_with("0x") {
-            let s = s.trim_start_matches("0x");
-            if let Ok(int) = i8::from_str_radix(s, 16) {
+            let hex = s.trim_start_matches("0x");
+            if let Ok(int) = i8::from_str_radix(hex, 16) {
                 Some(ParamKind::I8(int))
-            } else if let Ok(int) = u8::from_str_radix(s, 16) {
+            } else if let Ok(int) = u8::from_str_radix(hex, 16) {
                 Some(ParamKind::U8(int))
-            } else if let Ok(int) = i16::from_str_radix(s, 16) {
+            } else if let Ok(int) = i16::from_str_radix(hex, 16) {
                 Some(ParamKind::I16(int))
-            } else if let Ok(int) = u16::from_str_radix(s, 16) {
+            } else if let Ok(int) = u16::from_str_radix(hex, 16) {
                 Some(ParamKind::U16(int))
-            } else if let Ok(int) = i32::from_str_radix(s, 16) {
+            } else if let Ok(int) = i32::from_str_radix(hex, 16) {
                 Some(ParamKind::I32(int))
-            } else if let Ok(int) = u32::from_str_radix(s, 16) {
+            } else if let Ok(int) = u32::from_str_radix(hex, 16) {
                 Some(ParamKind::U32(int))
             } else {
-                Hash40::from_hex_str(s).ok().map(|x| ParamKind::Hash(x))
+                Hash40::from_hex_str(s).ok().map(ParamKind::Hash)
             }
         } else if let Ok(int) = s.parse() {
             Some(ParamKind::I8(int))
@@ -129,12 +169,30 @@ impl Diff {
         } else if let Ok(float) = s.parse() {
             Some(ParamKind::Float(float))
         } else {
-            Some(ParamKind::Hash(to_hash40(s)))
+            Some(ParamKind::Hash(labels.hash_label(s)))
+        }
+    }
+
+    fn find_diffs_in_param(path: &mut PrcKey, param: &ParamKind, modded_param: &ParamKind, report: &mut GenerateReport) {
+        if modded_param == param {
+            return;
+        }
+
+        match (param, modded_param) {
+            (ParamKind::Struct(s), ParamKind::Struct(s2)) => Self::find_diffs_in_struct(path, s, s2, report),
+            (ParamKind::List(l), ParamKind::List(l2)) => Self::find_diffs_in_list(path, l, l2, report),
+            (ParamKind::Struct(_), _) | (ParamKind::List(_), _) | (_, ParamKind::Struct(_)) | (_, ParamKind::List(_)) => {
+                report.skipped.push(Error::TypeMismatch {
+                    key: path.clone(),
+                    expected: type_name(param),
+                    found: type_name(modded_param)
+                });
+            },
+            _ => report.diff.0.push((path.clone(), modded_param.clone()))
         }
     }
 
-    fn find_diffs_in_struct(source: &ParamStruct, modded: &ParamStruct) -> Vec<(PrcKey, ParamKind)> {
-        let mut vec = vec![];
+    fn find_diffs_in_struct(path: &mut PrcKey, source: &ParamStruct, modded: &ParamStruct, report: &mut GenerateReport) {
         for (key, param) in source.0.iter() {
             let mut modded_param = None;
             for (m_key, m_param) in modded.0.iter() {
@@ -143,172 +201,454 @@ impl Diff {
                 }
             }
 
-            if let Some (modded_param) = modded_param {
-                if modded_param == param {
-                    continue;
-                }
+            path.push(PrcKeyType::StructField(*key));
+            match modded_param {
+                Some(modded_param) => Self::find_diffs_in_param(path, param, modded_param, report),
+                None => report.skipped.push(Error::Unpatchable { key: path.clone(), change: "was removed" })
+            }
+            path.pop();
+        }
 
-                match param {
-                    ParamKind::Struct(s) => {
-                        if let ParamKind::Struct(s2) = modded_param {
-                            vec.extend(
-                                Self::find_diffs_in_struct(s, s2)
-                                    .into_iter()
-                                    .map(|(x, y)| (PrcKey {
-                                        ty: PrcKeyType::StructField(*key),
-                                        next: Some(Box::new(x))
-                                    }, y))
-                            );
-                        } else {
-                            unreachable!()
-                        }
-                    },
-                    ParamKind::List(l) => {
-                        if let ParamKind::List(l2) = modded_param {
-                            vec.extend(
-                                Self::find_diffs_in_list(l, l2)
-                                    .into_iter()
-                                    .map(|(x, y)| (PrcKey {
-                                        ty: PrcKeyType::StructField(*key),
-                                        next: Some(Box::new(x))
-                                    }, y))
-                            );
-                        } else {
-                            unreachable!()
-                        }
-                    },
-                    _ => {
-                        vec.push((PrcKey {
-                            ty: PrcKeyType::StructField(*key),
-                            next: None
-                        }, modded_param.clone()))
-                    }
-                }
+        for (key, _) in modded.0.iter().filter(|(key, _)| !source.0.iter().any(|(other, _)| other == key)) {
+            path.push(PrcKeyType::StructField(*key));
+            report.skipped.push(Error::Unpatchable { key: path.clone(), change: "was added" });
+            path.pop();
+        }
+    }
+
+    fn find_diffs_in_list(path: &mut PrcKey, source: &ParamList, modded: &ParamList, report: &mut GenerateReport) {
+        for idx in 0..source.0.len().max(modded.0.len()) {
+            path.push(PrcKeyType::ListIndex(idx));
+            match (source.0.get(idx), modded.0.get(idx)) {
+                (Some(param), Some(modded_param)) => Self::find_diffs_in_param(path, param, modded_param, report),
+                (Some(_), None) => report.skipped.push(Error::Unpatchable { key: path.clone(), change: "was removed" }),
+                _ => report.skipped.push(Error::Unpatchable { key: path.clone(), change: "was added" })
             }
+            path.pop();
         }
+    }
 
-        vec
+    /// Reads a single value the way it is written in a text patch, such as `1.5`, `hash40(label)`
+    /// or `"string"`. Values can't span lines, so anything with a line break in it is rejected.
+    pub fn parse_value(s: &str) -> Option<ParamKind> {
+        Self::parse_value_with(s, Labels::global())
     }
 
-    fn find_diffs_in_list(source: &ParamList, modded: &ParamList) -> Vec<(PrcKey, ParamKind)> {
-        let mut vec = vec![];
-        for (idx, param) in source.0.iter().enumerate() {
-            if let Some(modded_param) = modded.0.get(idx) {
-                if modded_param == param {
-                    continue;
-                }
+    /// Like [`parse_value`](Self::parse_value), with labels harvested into `labels` instead of the global table
+    pub fn parse_value_with(s: &str, labels: &Labels) -> Option<ParamKind> {
+        if s.contains(['\n', '\r']) {
+            return None;
+        }
+        Self::get_param_kind_from_str(s.trim(), labels)
+    }
 
-                match param {
-                    ParamKind::Struct(s) => {
-                        if let ParamKind::Struct(s2) = modded_param {
-                            vec.extend(
-                                Self::find_diffs_in_struct(s, s2)
-                                    .into_iter()
-                                    .map(|(x, y)| (PrcKey {
-                                        ty: PrcKeyType::ListIndex(idx),
-                                        next: Some(Box::new(x))
-                                    }, y))
-                            );
-                        } else {
-                            unreachable!()
-                        }
-                    },
-                    ParamKind::List(l) => {
-                        if let ParamKind::List(l2) = modded_param {
-                            vec.extend(
-                                Self::find_diffs_in_list(l, l2)
-                                    .into_iter()
-                                    .map(|(x, y)| (PrcKey {
-                                        ty: PrcKeyType::ListIndex(idx),
-                                        next: Some(Box::new(x))
-                                    }, y))
-                            );
-                        } else {
-                            unreachable!()
+    /// Like [`parse_value`](Self::parse_value), but plain numbers like `5` are read as the type of `like`,
+    /// the param they are going to replace, instead of the smallest type they fit in. Values written with
+    /// their type, like `5i8`, keep it.
+    pub fn parse_value_as(s: &str, like: &ParamKind) -> Option<ParamKind> {
+        Self::parse_value_as_with(s, like, Labels::global())
+    }
+
+    /// Like [`parse_value_as`](Self::parse_value_as), with labels harvested into `labels` instead of the global table
+    pub fn parse_value_as_with(s: &str, like: &ParamKind, labels: &Labels) -> Option<ParamKind> {
+        if s.contains(['\n', '\r']) {
+            return None;
+        }
+        let s = s.trim();
+        if let Some(param) = dump::parse_value(s, labels) {
+            return Some(param);
+        }
+
+        let param = match like {
+            ParamKind::I8(_) => untyped_int(s, i8::from_str_radix).map(ParamKind::I8),
+            ParamKind::U8(_) => untyped_int(s, u8::from_str_radix).map(ParamKind::U8),
+            ParamKind::I16(_) => untyped_int(s, i16::from_str_radix).map(ParamKind::I16),
+            ParamKind::U16(_) => untyped_int(s, u16::from_str_radix).map(ParamKind::U16),
+            ParamKind::I32(_) => untyped_int(s, i32::from_str_radix).map(ParamKind::I32),
+            ParamKind::U32(_) => untyped_int(s, u32::from_str_radix).map(ParamKind::U32),
+            ParamKind::Float(_) => s.parse().ok().map(ParamKind::Float),
+            ParamKind::Hash(_) if s.starts_with("0x") => Hash40::from_hex_str(s).ok().map(ParamKind::Hash),
+            _ => None
+        };
+        // Anything that doesn't fit is read as usual, so applying it reports the mismatch
+        param.or_else(|| Self::get_param_kind_from_str(s, labels))
+    }
+
+    fn from_text(data: &str, labels: &Labels) -> Result<Self> {
+        let mut patches = vec![];
+        for (line_idx, line) in data.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (key, value) = split_assignment(line)
+                .ok_or_else(|| Error::parse(line_idx + 1, 1, "expected `key = value`"))?;
+            let key_str = key.trim();
+            let key = PrcKey::parse_with(key_str, labels).map_err(|e| e.within(data, key_str))?;
+            let value = value.trim();
+            let param = Self::get_param_kind_from_str(value, labels)
+                .ok_or_else(|| Error::parse_at(data, value, format!("malformed value `{}`", value)))?;
+            patches.push((key, param));
+        }
+        Ok(Self(patches))
+    }
+
+    // Values are written with their type, like a full dump, so that they read back as the same kind of param
+    fn to_text(&self, labels: &Labels) -> Result<String> {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        for (path, param) in self.0.iter() {
+            let value = dump::write_value(param, labels).ok_or_else(|| Error::TypeMismatch {
+                key: path.clone(),
+                expected: "a value",
+                found: type_name(param)
+            })?;
+            let _ = writeln!(&mut out, "{} = {}", path.to_string_with(labels), value);
+        }
+        Ok(out)
+    }
+
+    fn from_binary(data: &[u8]) -> Result<(Self, BTreeMap<Hash40, String>)> {
+        let data = match data.strip_prefix(MAGIC) {
+            Some([BINARY_VERSION, data @ ..]) => data,
+            Some([LABELED_BINARY_VERSION, data @ ..]) => {
+                let labeled = bincode::deserialize::<Labeled>(data)?;
+                return Ok((labeled.patch, labeled.labels));
+            },
+            Some([version, ..]) => return Err(Error::UnsupportedVersion(*version)),
+            Some([]) => return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into())),
+            None => data
+        };
+        Ok((bincode::deserialize::<Self>(data)?, BTreeMap::new()))
+    }
+
+    fn from_json(data: &[u8]) -> Result<(Self, BTreeMap<Hash40, String>)> {
+        // Plain patches are a JSON array, patches with embedded labels are an object
+        if std::str::from_utf8(data)?.trim_start_matches('\u{feff}').trim_start().starts_with('{') {
+            let labeled = serde_json::from_slice::<Labeled>(data)?;
+            Ok((labeled.patch, labeled.labels))
+        } else {
+            Ok((serde_json::from_slice::<Self>(data)?, BTreeMap::new()))
+        }
+    }
+
+    /// Reads a patch in the given [`Format`]. Both binary formats are accepted for
+    /// [`Format::Binary`] and [`Format::LegacyBinary`].
+    /// Labels embedded in the patch are added to the global table.
+    pub fn from_bytes_as(data: &[u8], format: Format) -> Result<Self> {
+        Self::from_bytes_as_with(data, format, Labels::global())
+    }
+
+    /// Like [`from_bytes_as`](Self::from_bytes_as), with embedded labels added to `labels` instead of
+    /// the global table. Fails if one of them collides under [`CollisionPolicy::Error`](crate::hash::CollisionPolicy::Error).
+    pub fn from_bytes_as_with(data: &[u8], format: Format, labels: &Labels) -> Result<Self> {
+        let (diff, embedded) = match format {
+            Format::Text => (Self::from_text(std::str::from_utf8(data)?, labels)?, BTreeMap::new()),
+            Format::Binary | Format::LegacyBinary => Self::from_binary(data)?,
+            Format::Json => Self::from_json(data)?,
+            Format::Xml => (format::parse_xml(std::str::from_utf8(data)?, labels).map(Self)?, BTreeMap::new())
+        };
+        for (hash, label) in embedded {
+            // A label that doesn't hash to its hash would make the patch print the wrong names
+            if hash40::to_hash40(&label) == hash {
+                labels.try_add(label, LabelSource::Embedded)?;
+            }
+        }
+        Ok(diff)
+    }
+
+    /// Reads a patch in any of the supported [`Format`]s, detecting which one it is from the contents.
+    /// Labels embedded in the patch are added to the global table.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        Self::from_bytes_as(data, Format::detect(data))
+    }
+
+    /// Like [`from_bytes`](Self::from_bytes), with embedded labels added to `labels` instead of the global table
+    pub fn from_bytes_with(data: &[u8], labels: &Labels) -> Result<Self> {
+        Self::from_bytes_as_with(data, Format::detect(data), labels)
+    }
+
+    /// Reads the rest of `reader` as a patch in the given [`Format`].
+    pub fn from_reader_as<R: Read>(mut reader: R, format: Format) -> Result<Self> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        Self::from_bytes_as(&data, format)
+    }
+
+    /// Reads the rest of `reader` as a patch, detecting the [`Format`] from the contents.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        Self::from_bytes(&data)
+    }
+
+    /// Encodes the patch in the given [`Format`].
+    pub fn to_bytes(&self, format: Format) -> Result<Vec<u8>> {
+        self.to_bytes_with(format, Labels::global())
+    }
+
+    /// Like [`to_bytes`](Self::to_bytes), with the labels of text and XML patches taken from `labels`
+    /// instead of the global table
+    pub fn to_bytes_with(&self, format: Format, labels: &Labels) -> Result<Vec<u8>> {
+        let data = match format {
+            Format::Text => self.to_text(labels)?.into_bytes(),
+            Format::Binary => {
+                let mut data = MAGIC.to_vec();
+                data.push(BINARY_VERSION);
+                bincode::serialize_into(&mut data, self)?;
+                data
+            },
+            Format::LegacyBinary => bincode::serialize(self)?,
+            Format::Json => serde_json::to_vec_pretty(self)?,
+            Format::Xml => format::write_xml(&self.0, labels)?.into_bytes()
+        };
+        Ok(data)
+    }
+
+    /// The labels in `labels` of every hash that the patch uses, in its keys and in its values
+    pub fn used_labels(&self, labels: &Labels) -> BTreeMap<Hash40, String> {
+        let mut used = BTreeMap::new();
+        let mut add = |hash: Hash40| {
+            if let Some(label) = labels.try_get(hash) {
+                used.insert(hash, label);
+            }
+        };
+        for (key, param) in self.0.iter() {
+            for segment in key.iter() {
+                match segment {
+                    PrcKeyType::StructField(hash) => add(*hash),
+                    PrcKeyType::ListSelect { field, value } => {
+                        add(*field);
+                        if let SelectorValue::Hash(hash) = value {
+                            add(*hash);
                         }
                     },
-                    _ => {
-                        vec.push((PrcKey {
-                            ty: PrcKeyType::ListIndex(idx),
-                            next: None
-                        }, modded_param.clone()))
-                    }
+                    _ => {}
                 }
             }
+            if let ParamKind::Hash(hash) = param {
+                add(*hash);
+            }
         }
+        used
+    }
 
-        vec
-    }
-
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
-        let data = std::fs::read_to_string(path)?;
-        let patches = data
-            .lines()
-            .map(|x| {
-                let splits = x.split("=").map(|x| x.trim()).collect::<Vec<&str>>();
-                (splits[0], splits[1])
-            })
-            .filter_map(|(key, value)| {
-                let key: PrcKey = key.parse().unwrap();
-                Self::get_param_kind_from_str(value).map(|x| (key, x))
-            })
-            .collect();
-        Ok(Self(patches))
+    /// Encodes the patch in the given [`Format`] along with the labels from `labels` that it uses, so that
+    /// it reads back with names on machines that don't have them. Text and XML patches spell their labels
+    /// out anyway, and [`Format::LegacyBinary`] has no room for them, so those are written as usual.
+    pub fn to_bytes_labeled(&self, format: Format, labels: &Labels) -> Result<Vec<u8>> {
+        let labeled = LabeledRef { labels: self.used_labels(labels), patch: self };
+        let data = match format {
+            Format::Binary => {
+                let mut data = MAGIC.to_vec();
+                data.push(LABELED_BINARY_VERSION);
+                bincode::serialize_into(&mut data, &labeled)?;
+                data
+            },
+            Format::Json => serde_json::to_vec_pretty(&labeled)?,
+            Format::Text | Format::LegacyBinary | Format::Xml => self.to_bytes_with(format, labels)?
+        };
+        Ok(data)
+    }
+
+    /// Writes the patch to `writer` in the given [`Format`].
+    pub fn to_writer<W: Write>(&self, mut writer: W, format: Format) -> Result<()> {
+        writer.write_all(&self.to_bytes(format)?)?;
+        Ok(())
+    }
+
+    /// Reads a patch in any of the supported [`Format`]s, detecting which one it is from the contents.
+    /// Labels embedded in the patch are added to the global table.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Like [`load`](Self::load), with embedded labels added to `labels` instead of the global table
+    pub fn load_with<P: AsRef<Path>>(path: P, labels: &Labels) -> Result<Self> {
+        Self::from_bytes_with(&std::fs::read(path)?, labels)
+    }
+
+    /// Writes the patch in the given [`Format`]. All of them can be read back with [`Diff::load`].
+    pub fn save_as<P: AsRef<Path>>(&self, path: P, format: Format) -> Result<()> {
+        self.save_as_with(path, format, Labels::global())
+    }
+
+    /// Like [`save_as`](Self::save_as), with the labels taken from `labels` instead of the global table
+    pub fn save_as_with<P: AsRef<Path>>(&self, path: P, format: Format, labels: &Labels) -> Result<()> {
+        std::fs::write(path, self.to_bytes_with(format, labels)?)?;
+        Ok(())
+    }
+
+    /// Writes the patch with the labels it uses embedded in it. See [`to_bytes_labeled`](Self::to_bytes_labeled).
+    pub fn save_labeled<P: AsRef<Path>>(&self, path: P, format: Format, labels: &Labels) -> Result<()> {
+        std::fs::write(path, self.to_bytes_labeled(format, labels)?)?;
+        Ok(())
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_bytes_as(&std::fs::read(path)?, Format::Text)
+    }
+
+    pub fn open_bin<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_bytes_as(&std::fs::read(path)?, Format::Binary)
     }
 
-    pub fn open_bin<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
-        let data = std::fs::read(path)?;
-        Ok(bincode::deserialize(&data).unwrap())
+    pub fn save<P: AsRef<Path>>(&self, path: P, is_text: bool) -> Result<()> {
+        self.save_with(path, is_text, Labels::global())
     }
 
-    pub fn save<P: AsRef<Path>>(&self, path: P, is_text: bool) -> Result<(), std::io::Error> {
+    pub fn save_with<P: AsRef<Path>>(&self, path: P, is_text: bool, labels: &Labels) -> Result<()> {
         if is_text {
-            let mut file = std::fs::File::create(path)?;
-            for (key, param) in self.0.iter() {
-                use std::io::Write;
-                match param {
-                    ParamKind::Struct(_) => unreachable!(),
-                    ParamKind::List(_) => unreachable!(),
-                    ParamKind::Bool(bool) => { let _ = write!(&mut file, "{} = {}\n", key.to_string(), bool); },
-                    ParamKind::I8(int) => { let _ = write!(&mut file, "{} = {}\n", key.to_string(), int); },    
-                    ParamKind::U8(int) => { let _ = write!(&mut file, "{} = {}\n", key.to_string(), int); },
-                    ParamKind::I16(int) => { let _ = write!(&mut file, "{} = {}\n", key.to_string(), int); },    
-                    ParamKind::U16(int) => { let _ = write!(&mut file, "{} = {}\n", key.to_string(), int); },   
-                    ParamKind::I32(int) => { let _ = write!(&mut file, "{} = {}\n", key.to_string(), int); },    
-                    ParamKind::U32(int) => { let _ = write!(&mut file, "{} = {}\n", key.to_string(), int); },   
-                    ParamKind::Float(float) => { let _ = write!(&mut file, "{} = {}\n", key.to_string(), float); },   
-                    ParamKind::Str(string) => { let _ = write!(&mut file, "{} = \"{}\"\n", key.to_string(), string); },   
-                    ParamKind::Hash(hash) => { let _ = write!(&mut file, "{} = {}\n", key.to_string(), crate::hash::get(*hash)); },   
+            self.save_as_with(path, Format::Text, labels)
+        } else {
+            self.save_as_with(path, Format::LegacyBinary, labels)
+        }
+    }
+
+    // The concrete keys that an entry writes to. Entries that end in `[+]` append to every list that the rest of the key covers.
+    fn targets(params: &ParamStruct, key: &PrcKey, labels: &Labels) -> std::result::Result<Vec<PrcKey>, ResolveError> {
+        match (key.last(), key.parent()) {
+            (Some(PrcKeyType::ListAppend), Some(parent)) if !parent.is_empty() => {
+                let mut lists = params.expand_with(&parent, labels)?;
+                for list in lists.iter_mut() {
+                    list.push(PrcKeyType::ListAppend);
                 }
+                Ok(lists)
+            },
+            _ => params.expand_with(key, labels)
+        }
+    }
+
+    /// Applies every entry of the patch, with list patterns like `[*]` set on every entry they cover
+    /// and entries ending in `[+]` added to the end of their list. Entries are applied in order, so `[-1]`
+    /// after a `[+]` points at the entry that was just added.
+    ///
+    /// Entries that don't point at a value in `params`, and values added to a list that holds a different type,
+    /// are skipped and listed in the report.
+    pub fn apply(self, params: &mut ParamStruct) -> ApplyReport {
+        self.apply_with(params, Labels::global())
+    }
+
+    /// Like [`apply`](Self::apply), with skipped entries naming fields by `labels` instead of the global table
+    pub fn apply_with(self, params: &mut ParamStruct, labels: &Labels) -> ApplyReport {
+        self.apply_entries(params, false, labels)
+    }
+
+    /// Applies every entry of the patch, but only if all of them point at an existing value of the same type,
+    /// or at a list of the same type for entries that end in `[+]`. Unlike [`Diff::apply`], nothing is changed
+    /// when any entry doesn't fit, and the error is the first entry that didn't.
+    pub fn try_apply(self, params: &mut ParamStruct) -> Result<()> {
+        self.try_apply_with(params, Labels::global())
+    }
+
+    /// Like [`try_apply`](Self::try_apply), with the error naming fields by `labels` instead of the global table
+    pub fn try_apply_with(self, params: &mut ParamStruct, labels: &Labels) -> Result<()> {
+        let mut patched = params.clone();
+        let report = self.apply_entries(&mut patched, true, labels);
+        match report.skipped.into_iter().next() {
+            Some(error) => Err(error),
+            None => {
+                *params = patched;
+                Ok(())
             }
-        } else {
-            let data = bincode::serialize(self).unwrap();
-            std::fs::write(path, &data)?;
         }
-        Ok(())
     }
 
-    pub fn apply(self, params: &mut ParamStruct) {
+    // With `same_type`, values are only replaced by values of the same type
+    fn apply_entries(self, params: &mut ParamStruct, same_type: bool, labels: &Labels) -> ApplyReport {
+        let mut report = ApplyReport::default();
         for (key, value) in self.0 {
-            if let Some(p) = Self::find_key_in_struct(&key, params) {
-                *p = value;
+            let keys = match Self::targets(params, &key, labels) {
+                Ok(keys) => keys,
+                Err(e) => {
+                    report.skipped.push(e.into());
+                    continue;
+                }
+            };
+            for key in keys {
+                if let Some(parent) = key.parent().filter(|_| key.last() == Some(&PrcKeyType::ListAppend)) {
+                    match params.get_with(&parent, labels) {
+                        Ok(ParamKind::List(list)) => match list.0.first() {
+                            Some(p) if std::mem::discriminant(p) != std::mem::discriminant(&value) => {
+                                report.skipped.push(Error::TypeMismatch { key, expected: type_name(p), found: type_name(&value) });
+                                continue;
+                            },
+                            _ => {}
+                        },
+                        Ok(p) => {
+                            report.skipped.push(Error::TypeMismatch { key: parent, expected: "list", found: type_name(p) });
+                            continue;
+                        },
+                        Err(e) => {
+                            report.skipped.push(e.into());
+                            continue;
+                        }
+                    }
+                    match params.insert_with(&key, value.clone(), labels) {
+                        Ok(()) => report.applied += 1,
+                        Err(e) => report.skipped.push(e.into())
+                    }
+                    continue;
+                }
+
+                match params.get_mut_with(&key, labels) {
+                    // Patches only ever replace values, so keys that point at a struct or list are left alone
+                    Ok(p @ ParamKind::Struct(_)) | Ok(p @ ParamKind::List(_)) => {
+                        report.skipped.push(Error::TypeMismatch {
+                            key,
+                            expected: type_name(p),
+                            found: type_name(&value)
+                        });
+                    },
+                    Ok(p) if same_type && std::mem::discriminant(p) != std::mem::discriminant(&value) => {
+                        report.skipped.push(Error::TypeMismatch {
+                            key,
+                            expected: type_name(p),
+                            found: type_name(&value)
+                        });
+                    },
+                    Ok(p) => {
+                        *p = value.clone();
+                        report.applied += 1;
+                    },
+                    Err(e) => report.skipped.push(e.into())
+                }
             }
         }
+        report
+    }
+
+    /// Finds every value that changed between `source` and `modded`.
+    ///
+    /// A patch can only change values, so fields and list entries that were added or removed, and structs
+    /// or lists that were replaced by a different type, are left out of the patch and listed in the report.
+    pub fn generate(source: &ParamStruct, modded: &ParamStruct) -> GenerateReport {
+        let mut report = GenerateReport { diff: Diff(vec![]), skipped: vec![] };
+        Self::find_diffs_in_struct(&mut PrcKey::new(), source, modded, &mut report);
+        report
     }
 
-    pub fn generate(source: &ParamStruct, modded: &ParamStruct) -> Self {
-        Self(Self::find_diffs_in_struct(source, modded))
+    /// Finds every value that changed between `source` and `modded`, but only if the patch can hold all
+    /// of the changes. Unlike [`Diff::generate`], the error is the first change that it can't.
+    pub fn try_generate(source: &ParamStruct, modded: &ParamStruct) -> Result<Self> {
+        let report = Self::generate(source, modded);
+        match report.skipped.into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(report.diff)
+        }
     }
 }
 
 #[test]
+#[ignore = "requires local fighter_param files"]
 fn write_diff() {
     let diffs = Diff(vec![
-        ("fighter_param_table[0].landing_attack_air_frame_n".parse().unwrap(), ParamKind::Float(0.0)),
-        ("fighter_param_table[0].landing_attack_air_frame_f".parse().unwrap(), ParamKind::Float(1.0)),
-        ("fighter_param_table[0].landing_attack_air_frame_b".parse().unwrap(), ParamKind::Float(2.0)),
-        ("fighter_param_table[0].landing_attack_air_frame_lw".parse().unwrap(), ParamKind::Float(3.0)),
-        ("fighter_param_table[0].landing_attack_air_frame_hi".parse().unwrap(), ParamKind::Float(4.0)),
+        (prc_key!("fighter_param_table[0].landing_attack_air_frame_n"), ParamKind::Float(0.0)),
+        (prc_key!("fighter_param_table[0].landing_attack_air_frame_f"), ParamKind::Float(1.0)),
+        (prc_key!("fighter_param_table[0].landing_attack_air_frame_b"), ParamKind::Float(2.0)),
+        (prc_key!("fighter_param_table[0].landing_attack_air_frame_lw"), ParamKind::Float(3.0)),
+        (prc_key!("fighter_param_table[0].landing_attack_air_frame_hi"), ParamKind::Float(4.0)),
     ]);
 
     let data = bincode::serialize(&diffs).unwrap();
@@ -316,8 +656,9 @@ fn write_diff() {
 }
 
 #[test]
+#[ignore = "requires local fighter_param files"]
 fn read_diff() {
-    hash::add_hashes(vec![
+    crate::hash::add_hashes(vec![
         "test",
         "test1",
         "test2",
@@ -328,7 +669,7 @@ fn read_diff() {
     ]);
     let diffs = std::fs::read("/home/blujay/dev/arc/prcx/test.prcx").unwrap();
     let diffs: Diff = bincode::deserialize(&diffs).unwrap();
-    let test_strs = vec![
+    let test_strs = [
         "test",
         "test1.test3",
         "test1.test4",
@@ -346,28 +687,337 @@ fn read_diff() {
 }
 
 #[test]
+#[ignore = "requires local fighter_param files"]
 fn apply_diff() {
     let diffs = Diff(vec![
-        ("fighter_param_table[0].walk_accel_mul".parse().unwrap(), ParamKind::Float(100.0))
+        (prc_key!("fighter_param_table[0].walk_accel_mul"), ParamKind::Float(100.0))
     ]);
     let mut params = prc::open("/home/blujay/dev/arc/prcx/fighter_param.prc").unwrap();
-    diffs.apply(&mut params);
+    let _ = diffs.apply(&mut params);
     prc::save("/home/blujay/dev/arc/prcx/fighter_param_out.prc", &params).unwrap();
 }
 
 #[test]
+#[ignore = "requires local fighter_param files"]
 fn read_and_apply_bin_diff() {
     let diffs = std::fs::read("/home/blujay/dev/arc/prcx/fighter_param_patch.prcx").unwrap();
     let diffs: Diff = bincode::deserialize(&diffs).unwrap();
     let mut params = prc::open("/home/blujay/dev/arc/prcx/fighter_param.prc").unwrap();
-    diffs.apply(&mut params);
+    let _ = diffs.apply(&mut params);
     prc::save("/home/blujay/dev/arc/prcx/fighter_param_out.prc", &params).unwrap();
 }
 
 #[test]
+#[ignore = "requires local fighter_param files"]
 fn read_and_apply_text_diff() {
     let diffs = Diff::open("/home/blujay/dev/arc/prcx/fighter_param_patch.prctxt").unwrap();
     let mut params = prc::open("/home/blujay/dev/arc/prcx/fighter_param.prc").unwrap();
-    diffs.apply(&mut params);
+    let _ = diffs.apply(&mut params);
     prc::save("/home/blujay/dev/arc/prcx/fighter_param_out.prc", &params).unwrap();
-}
\ No newline at end of file
+}
+#[test]
+fn load_detects_format() {
+    let diffs = Diff(vec![
+        (prc_key!("format_test_table[2].format_test_float"), ParamKind::Float(1.5)),
+        (prc_key!("format_test_kind"), ParamKind::Hash(to_hash40("format_test_kind_value"))),
+        (prc_key!("format_test_str"), ParamKind::Str("<a & b>".to_string())),
+    ]);
+
+    let dir = std::env::temp_dir();
+    for (idx, format) in [Format::Text, Format::Binary, Format::LegacyBinary, Format::Json, Format::Xml].iter().enumerate() {
+        // Use the same misleading extension for everything, only the contents should matter
+        let path = dir.join(format!("prcx_load_detects_format_{}_{}.prcx", std::process::id(), idx));
+        diffs.save_as(&path, *format).unwrap();
+        assert_eq!(Format::detect(&std::fs::read(&path).unwrap()), *format);
+        let loaded = Diff::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(diffs.to_text(Labels::global()).unwrap(), loaded.to_text(Labels::global()).unwrap(), "{:?} did not round trip", format);
+    }
+}
+
+#[test]
+fn reader_writer_roundtrip() {
+    let diffs = Diff(vec![
+        (prc_key!("io_test_table[0].io_test_float"), ParamKind::Float(-2.5)),
+        (prc_key!("io_test_flag"), ParamKind::Bool(false)),
+    ]);
+
+    for format in [Format::Text, Format::Binary, Format::LegacyBinary, Format::Json, Format::Xml].iter() {
+        let mut data = vec![];
+        diffs.to_writer(&mut data, *format).unwrap();
+        let loaded = Diff::from_reader(std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(diffs.to_text(Labels::global()).unwrap(), loaded.to_text(Labels::global()).unwrap(), "{:?} did not round trip", format);
+        let loaded = Diff::from_bytes_as(&data, *format).unwrap();
+        assert_eq!(diffs.to_text(Labels::global()).unwrap(), loaded.to_text(Labels::global()).unwrap(), "{:?} did not round trip", format);
+    }
+}
+
+#[test]
+fn malformed_patch_errors() {
+    match Diff::from_bytes(b"io_test_flag = true\nno equals sign here\n") {
+        Err(Error::Parse { line: 2, column: 1, .. }) => {},
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Malformed text patch was accepted")
+    }
+
+    match Diff::from_bytes(b"<prcx>\n  <float key=\"io_test_float\">1.0</float>\n  <float key=\"io_test_float\">1.0\n</prcx>") {
+        Err(Error::Parse { line: 3, column: 3, .. }) => {},
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Malformed XML patch was accepted")
+    }
+
+    for (patch, column) in [("io_test_flag = 0xZZ", 16), ("io_test_flag =  hash40(0xZZ)", 17), ("io_test_flag = \"a", 16)].iter() {
+        match Diff::from_bytes(patch.as_bytes()) {
+            Err(Error::Parse { line: 1, column: c, .. }) if c == *column => {},
+            Err(e) => panic!("Unexpected error for `{}`: {}", patch, e),
+            Ok(_) => panic!("Malformed value in `{}` was accepted", patch)
+        }
+    }
+
+    assert!(matches!(Diff::from_bytes(b"PRCX\x09"), Err(Error::UnsupportedVersion(9))));
+    assert!(matches!(Diff::from_bytes(&[3, 0, 0, 0, 0, 0, 0, 0, 1]), Err(Error::Binary(_))));
+}
+
+#[test]
+fn parse_value_test() {
+    assert_eq!(Diff::parse_value(" 1.5 "), Some(ParamKind::Float(1.5)));
+    assert_eq!(Diff::parse_value("3u16"), Some(ParamKind::U16(3)));
+    assert_eq!(Diff::parse_value("\"a\\\"b\""), Some(ParamKind::Str("a\"b".to_string())));
+    assert_eq!(Diff::parse_value("1\nother = 2"), None);
+    assert_eq!(Diff::parse_value("hash40(a"), None);
+
+    assert_eq!(Diff::parse_value_as("5", &ParamKind::U8(0)), Some(ParamKind::U8(5)));
+    assert_eq!(Diff::parse_value_as("0xff", &ParamKind::U32(0)), Some(ParamKind::U32(255)));
+    assert_eq!(Diff::parse_value_as(" 5 ", &ParamKind::Float(0.0)), Some(ParamKind::Float(5.0)));
+    assert_eq!(Diff::parse_value_as("0x10", &ParamKind::Hash(Hash40(0))), Some(ParamKind::Hash(Hash40(0x10))));
+    assert_eq!(Diff::parse_value_as("5i8", &ParamKind::U8(0)), Some(ParamKind::I8(5)));
+    assert_eq!(Diff::parse_value_as("300", &ParamKind::U8(0)), Some(ParamKind::I16(300)));
+    assert_eq!(Diff::parse_value_as("5\n", &ParamKind::U8(0)), None);
+}
+
+#[test]
+fn generate_and_try_apply_errors() {
+    let source = ParamStruct(vec![
+        (to_hash40("error_test_table"), ParamKind::List(ParamList(vec![
+            ParamKind::Struct(ParamStruct(vec![(to_hash40("error_test_float"), ParamKind::Float(1.0))])),
+        ]))),
+    ]);
+    let modded = ParamStruct(vec![
+        (to_hash40("error_test_table"), ParamKind::List(ParamList(vec![ParamKind::Float(1.0)]))),
+    ]);
+    match Diff::try_generate(&source, &modded) {
+        Err(Error::TypeMismatch { expected: "struct", found: "float", .. }) => {},
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Type change was accepted")
+    }
+    let report = Diff::generate(&source, &modded);
+    assert!(!report.is_complete());
+    assert!(report.diff.0.is_empty());
+    assert!(matches!(report.skipped.as_slice(), [Error::TypeMismatch { expected: "struct", found: "float", .. }]));
+
+    // Entries that were added or removed are reported with the changes that did fit
+    let lists = |values: &[u8]| ParamStruct(vec![
+        (to_hash40("error_test_list"), ParamKind::List(ParamList(values.iter().copied().map(ParamKind::U8).collect()))),
+        (to_hash40("error_test_other"), ParamKind::List(ParamList(values.iter().copied().map(ParamKind::U8).collect()))),
+    ]);
+    let (mut before, mut after) = (lists(&[0, 1]), lists(&[2, 1, 3]));
+    if let ParamKind::List(list) = &mut before.0[1].1 { list.0.push(ParamKind::U8(4)); }
+    if let ParamKind::List(list) = &mut after.0[1].1 { list.0.truncate(1); }
+    let report = Diff::generate(&before, &after);
+    assert_eq!(report.diff.0, vec![(prc_key!("error_test_list[0]"), ParamKind::U8(2)), (prc_key!("error_test_other[0]"), ParamKind::U8(2))]);
+    let skipped: Vec<_> = report.skipped.iter().map(|error| match error {
+        Error::Unpatchable { key, change } => (key.clone(), *change),
+        error => panic!("Unexpected error: {}", error)
+    }).collect();
+    assert_eq!(skipped, vec![
+        (prc_key!("error_test_list[2]"), "was added"),
+        (prc_key!("error_test_other[1]"), "was removed"),
+        (prc_key!("error_test_other[2]"), "was removed"),
+    ]);
+    assert!(matches!(Diff::try_generate(&before, &after), Err(Error::Unpatchable { change: "was added", .. })));
+
+    let mut params = source.clone();
+    let diffs = Diff(vec![
+        (prc_key!("error_test_table[0].error_test_float"), ParamKind::Float(2.0)),
+        (prc_key!("error_test_table[1].error_test_float"), ParamKind::Float(2.0)),
+    ]);
+    assert!(matches!(diffs.try_apply(&mut params), Err(Error::UnresolvedKey(_))));
+    assert_eq!(params, source);
+
+    let diffs = Diff(vec![
+        (prc_key!("error_test_table[0].error_test_float"), ParamKind::I32(2)),
+    ]);
+    assert!(matches!(diffs.try_apply(&mut params), Err(Error::TypeMismatch { expected: "float", found: "i32", .. })));
+
+    let diffs = Diff(vec![
+        (prc_key!("error_test_table[0].error_test_float"), ParamKind::Float(2.0)),
+    ]);
+    diffs.try_apply(&mut params).unwrap();
+    assert_eq!(Diff::try_generate(&source, &params).unwrap().0.len(), 1);
+}
+
+#[test]
+fn apply_list_pattern_test() {
+    let entry = || ParamKind::Struct(ParamStruct(vec![(to_hash40("pattern_test_float"), ParamKind::Float(0.0))]));
+    let mut params = ParamStruct(vec![
+        (to_hash40("pattern_test_table"), ParamKind::List(ParamList(vec![entry(), entry(), entry(), entry()]))),
+    ]);
+
+    let diff = Diff::from_bytes(b"pattern_test_table[1..3].pattern_test_float = 2.0f32\npattern_test_table[*].pattern_test_missing = 1.0f32\n").unwrap();
+    let report = diff.apply(&mut params);
+    assert_eq!(report.skipped.len(), 1);
+    let floats: Vec<_> = (0..4)
+        .map(|i| params.get(&format!("pattern_test_table[{}].pattern_test_float", i).parse().unwrap()).unwrap().clone())
+        .collect();
+    assert_eq!(floats, vec![ParamKind::Float(0.0), ParamKind::Float(2.0), ParamKind::Float(2.0), ParamKind::Float(0.0)]);
+
+    let diff = Diff(vec![("pattern_test_table[*].pattern_test_float".parse().unwrap(), ParamKind::Float(3.0))]);
+    diff.try_apply(&mut params).unwrap();
+    assert_eq!(params.get(&"pattern_test_table[3].pattern_test_float".parse().unwrap()), Ok(&ParamKind::Float(3.0)));
+}
+
+#[test]
+fn apply_selector_survives_reorder_test() {
+    let entry = |kind: &str| ParamKind::Struct(ParamStruct(vec![
+        (to_hash40("selector_test_kind"), ParamKind::Hash(to_hash40(kind))),
+        (to_hash40("selector_test_speed"), ParamKind::Float(1.0)),
+    ]));
+    let diff = Diff::from_bytes(b"selector_test_table[selector_test_kind=selector_test_b].selector_test_speed = 2.0f32").unwrap();
+
+    for order in [["selector_test_a", "selector_test_b"], ["selector_test_b", "selector_test_a"]].iter() {
+        let mut params = ParamStruct(vec![
+            (to_hash40("selector_test_table"), ParamKind::List(ParamList(order.iter().map(|kind| entry(kind)).collect()))),
+        ]);
+        Diff(diff.0.clone()).try_apply(&mut params).unwrap();
+        let b = order.iter().position(|kind| *kind == "selector_test_b").unwrap();
+        let key: PrcKey = format!("selector_test_table[{}].selector_test_speed", b).parse().unwrap();
+        assert_eq!(params.get(&key), Ok(&ParamKind::Float(2.0)));
+        assert_eq!(params.get(&key.parent().unwrap().join(&"selector_test_kind".parse().unwrap())), Ok(&ParamKind::Hash(to_hash40("selector_test_b"))));
+    }
+}
+
+#[test]
+fn apply_report_test() {
+    crate::hash::add_hashes(vec!["report_test_speed", "report_test_table"]);
+    let mut params = ParamStruct(vec![
+        (to_hash40("report_test_speed"), ParamKind::Float(1.0)),
+        (to_hash40("report_test_table"), ParamKind::List(ParamList(vec![ParamKind::U8(0), ParamKind::U8(1)]))),
+    ]);
+
+    let diff = Diff::from_bytes(b"report_test_sped = 2.0f32\nreport_test_table[*] = 5u8\nreport_test_table = 1u8").unwrap();
+    let report = diff.apply(&mut params);
+    assert_eq!(report.applied, 2);
+    assert!(!report.is_complete());
+    assert_eq!(report.skipped.len(), 2);
+    assert!(report.to_string().contains("did you mean `report_test_speed`?"), "{}", report);
+    assert_eq!(params.get(&"report_test_table[1]".parse().unwrap()), Ok(&ParamKind::U8(5)));
+}
+
+#[test]
+fn apply_append_test() {
+    let mut params = ParamStruct(vec![
+        (to_hash40("append_test_list"), ParamKind::List(ParamList(vec![ParamKind::U8(0)]))),
+        (to_hash40("append_test_value"), ParamKind::U8(0)),
+    ]);
+
+    let diff = Diff::from_bytes(b"append_test_list[+] = 1u8\nappend_test_list[+] = 2u8\nappend_test_list[-1] = 3u8").unwrap();
+    let report = diff.apply(&mut params);
+    assert!(report.is_complete(), "{}", report);
+    assert_eq!(params.get(&"append_test_list".parse().unwrap()), Ok(&ParamKind::List(ParamList(vec![ParamKind::U8(0), ParamKind::U8(1), ParamKind::U8(3)]))));
+
+    let diff = Diff::from_bytes(b"append_test_value[+] = 1u8").unwrap();
+    assert!(matches!(diff.try_apply(&mut params), Err(Error::TypeMismatch { expected: "list", found: "u8", .. })));
+
+    let diff = Diff::from_bytes(b"append_test_list[+] = 1.5f32").unwrap();
+    assert!(matches!(diff.try_apply(&mut params), Err(Error::TypeMismatch { expected: "u8", found: "float", .. })));
+
+    let diff = Diff::from_bytes(b"append_test_list[+] = 4u8\nappend_test_list[-1] = 1.5f32").unwrap();
+    assert!(matches!(diff.try_apply(&mut params), Err(Error::TypeMismatch { expected: "u8", found: "float", .. })));
+    assert_eq!(params.get(&"append_test_list".parse().unwrap()), Ok(&ParamKind::List(ParamList(vec![ParamKind::U8(0), ParamKind::U8(1), ParamKind::U8(3)]))));
+
+    let mut params = ParamStruct(vec![(to_hash40("append_test_list"), ParamKind::List(ParamList(vec![])))]);
+    let diff = Diff::from_bytes(b"append_test_list[+] = 1u8\nappend_test_list[-1] = 2u8").unwrap();
+    diff.try_apply(&mut params).unwrap();
+    assert_eq!(params.get(&"append_test_list".parse().unwrap()), Ok(&ParamKind::List(ParamList(vec![ParamKind::U8(2)]))));
+}
+
+#[test]
+fn separate_labels_text_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["separate_diff_table", "separate_diff_kind"]);
+    let diff = Diff(vec![(prc_key!("separate_diff_table[0]"), ParamKind::Hash(to_hash40("separate_diff_kind")))]);
+
+    let text = String::from_utf8(diff.to_bytes_with(Format::Text, &labels).unwrap()).unwrap();
+    assert_eq!(text, "separate_diff_table[0] = hash40(separate_diff_kind)\n");
+    let global = String::from_utf8(diff.to_bytes(Format::Text).unwrap()).unwrap();
+    assert!(global.starts_with("0x"), "{}", global);
+}
+
+#[test]
+fn embedded_labels_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["embed_table", "embed_kind", "embed_value", "embed_unused"]);
+    let diff = Diff(vec![
+        (prc_key!("embed_table[embed_kind=embed_value].0x1234567890"), ParamKind::Hash(to_hash40("embed_value"))),
+        (prc_key!("embed_table[0].embed_kind"), ParamKind::Float(1.0))
+    ]);
+    let used = diff.used_labels(&labels);
+    assert_eq!(used.len(), 3);
+    assert!(!used.contains_key(&to_hash40("embed_unused")));
+
+    for format in [Format::Binary, Format::Json].iter() {
+        let data = diff.to_bytes_labeled(*format, &labels).unwrap();
+        let loaded_labels = Labels::new();
+        let loaded = Diff::from_bytes_with(&data, &loaded_labels).unwrap();
+        assert_eq!(loaded.0, diff.0, "{:?}", format);
+        assert_eq!(loaded_labels.len(), 3, "{:?}", format);
+        assert_eq!(loaded_labels.source(to_hash40("embed_kind")), Some(LabelSource::Embedded));
+        assert_eq!(
+            String::from_utf8(loaded.to_bytes_with(Format::Text, &loaded_labels).unwrap()).unwrap(),
+            String::from_utf8(diff.to_bytes_with(Format::Text, &labels).unwrap()).unwrap()
+        );
+    }
+
+    // Patches without labels keep the old layout
+    assert_eq!(diff.to_bytes(Format::Binary).unwrap()[4], BINARY_VERSION);
+}
+
+#[test]
+fn text_roundtrip_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["text_test_kind", "0xtext_test_hex"]);
+    let diff = Diff(vec![
+        (prc_key!("text_test_bool"), ParamKind::Bool(true)),
+        (prc_key!("text_test_i8"), ParamKind::I8(1)),
+        (prc_key!("text_test_u8"), ParamKind::U8(1)),
+        (prc_key!("text_test_i16"), ParamKind::I16(-300)),
+        (prc_key!("text_test_u16"), ParamKind::U16(300)),
+        (prc_key!("text_test_i32"), ParamKind::I32(5)),
+        (prc_key!("text_test_u32"), ParamKind::U32(5)),
+        (prc_key!("text_test_float"), ParamKind::Float(2.0)),
+        (prc_key!("text_test_hash"), ParamKind::Hash(to_hash40("text_test_kind"))),
+        (prc_key!("text_test_hex"), ParamKind::Hash(to_hash40("0xtext_test_hex"))),
+        (prc_key!("text_test_unlabeled"), ParamKind::Hash(to_hash40("text_test_unlabeled_kind"))),
+        (prc_key!("text_test_str"), ParamKind::Str("a\"b\\\nc = d".to_string())),
+    ]);
+
+    let text = diff.to_bytes_with(Format::Text, &labels).unwrap();
+    let loaded = Diff::from_bytes_as(&text, Format::Text).unwrap();
+    assert_eq!(loaded.0, diff.0, "{}", String::from_utf8_lossy(&text));
+}
+
+#[test]
+fn xml_roundtrip_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["xml_test_kind", "0xdead", " xml test walk "]);
+    let diff = Diff(vec![
+        (prc_key!("xml_test_hash"), ParamKind::Hash(to_hash40("xml_test_kind"))),
+        (prc_key!("xml_test_hex"), ParamKind::Hash(to_hash40("0xdead"))),
+        (prc_key!("xml_test_spaced"), ParamKind::Hash(to_hash40(" xml test walk "))),
+        (prc_key!("xml_test_str"), ParamKind::Str(" a <b> & c ".to_string())),
+    ]);
+
+    let xml = diff.to_bytes_with(Format::Xml, &labels).unwrap();
+    let loaded = Diff::from_bytes_as_with(&xml, Format::Xml, &labels).unwrap();
+    assert_eq!(loaded.0, diff.0, "{}", String::from_utf8_lossy(&xml));
+}
@@ -0,0 +1,421 @@
+use std::{
+    path::Path,
+    fmt::Write
+};
+
+use prc::{
+    ParamKind,
+    ParamStruct,
+    ParamList,
+    hash40::Hash40
+};
+
+use crate::{
+    access::ParamAccess,
+    error::{Error, Result},
+    hash::Labels,
+    key::{
+        needs_quotes,
+        quote,
+        split_assignment,
+        PrcKey,
+        PrcKeyType
+    }
+};
+
+#[cfg(test)]
+use prc::hash40::to_hash40;
+
+// The container part of the tree that the next line is written into
+enum Node<'a> {
+    Struct(&'a mut ParamStruct),
+    List(&'a mut ParamList)
+}
+
+/// Formats a leaf param with its type attached, so that it can be read back without guessing.
+/// Returns `None` for structs and lists, which are written as `struct(len)` and `list(len)` instead.
+pub fn write_value(param: &ParamKind, labels: &Labels) -> Option<String> {
+    let value = match param {
+        ParamKind::Struct(_) | ParamKind::List(_) => return None,
+        ParamKind::Bool(bool) => bool.to_string(),
+        ParamKind::I8(int) => format!("{}i8", int),
+        ParamKind::U8(int) => format!("{}u8", int),
+        ParamKind::I16(int) => format!("{}i16", int),
+        ParamKind::U16(int) => format!("{}u16", int),
+        ParamKind::I32(int) => format!("{}i32", int),
+        ParamKind::U32(int) => format!("{}u32", int),
+        ParamKind::Float(float) => format!("{}f32", float),
+        // Labels that start with `0x` would be read back as a hash, so those are quoted just like in keys
+        ParamKind::Hash(hash) => match labels.try_get(*hash) {
+            Some(label) if needs_quotes(&label) => format!("hash40({})", quote(&label)),
+            Some(label) => format!("hash40({})", label),
+            None => format!("hash40({:#x})", hash.0)
+        },
+        ParamKind::Str(string) => {
+            let mut escaped = String::with_capacity(string.len() + 2);
+            escaped.push('"');
+            for c in string.chars() {
+                match c {
+                    '"' => escaped.push_str("\\\""),
+                    '\\' => escaped.push_str("\\\\"),
+                    '\n' => escaped.push_str("\\n"),
+                    '\r' => escaped.push_str("\\r"),
+                    '\t' => escaped.push_str("\\t"),
+                    c => escaped.push(c)
+                }
+            }
+            escaped.push('"');
+            escaped
+        }
+    };
+    Some(value)
+}
+
+/// Parses a leaf param written by [`write_value`]. Untyped literals are not accepted.
+pub(crate) fn parse_value(s: &str, labels: &Labels) -> Option<ParamKind> {
+    if s == "true" {
+        return Some(ParamKind::Bool(true));
+    } else if s == "false" {
+        return Some(ParamKind::Bool(false));
+    }
+
+    if let Some(inner) = s.strip_prefix("hash40(").and_then(|x| x.strip_suffix(")")) {
+        let hash = if let Some(quoted) = inner.strip_prefix('"') {
+            labels.hash_label(&PrcKey::unquote(quoted)?)
+        } else if inner.starts_with("0x") {
+            Hash40::from_hex_str(inner).ok()?
+        } else {
+            labels.hash_label(inner)
+        };
+        return Some(ParamKind::Hash(hash));
+    }
+
+    if let Some(inner) = s.strip_prefix("\"").and_then(|x| x.strip_suffix("\"")) {
+        let mut string = String::with_capacity(inner.len());
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next()? {
+                    '"' => string.push('"'),
+                    '\\' => string.push('\\'),
+                    'n' => string.push('\n'),
+                    'r' => string.push('\r'),
+                    't' => string.push('\t'),
+                    _ => return None
+                },
+                // An unescaped quote means the string ended early
+                '"' => return None,
+                c => string.push(c)
+            }
+        }
+        return Some(ParamKind::Str(string));
+    }
+
+    if let Some(float) = s.strip_suffix("f32") {
+        float.parse().ok().map(ParamKind::Float)
+    } else if let Some(int) = s.strip_suffix("i8") {
+        int.parse().ok().map(ParamKind::I8)
+    } else if let Some(int) = s.strip_suffix("u8") {
+        int.parse().ok().map(ParamKind::U8)
+    } else if let Some(int) = s.strip_suffix("i16") {
+        int.parse().ok().map(ParamKind::I16)
+    } else if let Some(int) = s.strip_suffix("u16") {
+        int.parse().ok().map(ParamKind::U16)
+    } else if let Some(int) = s.strip_suffix("i32") {
+        int.parse().ok().map(ParamKind::I32)
+    } else if let Some(int) = s.strip_suffix("u32") {
+        int.parse().ok().map(ParamKind::U32)
+    } else {
+        None
+    }
+}
+
+fn write_struct(out: &mut String, key: &mut PrcKey, params: &ParamStruct, labels: &Labels) {
+    for (hash, param) in params.0.iter() {
+        key.push(PrcKeyType::StructField(*hash));
+        write_param(out, key, param, labels);
+        key.pop();
+    }
+}
+
+fn write_list(out: &mut String, key: &mut PrcKey, params: &ParamList, labels: &Labels) {
+    for (idx, param) in params.0.iter().enumerate() {
+        key.push(PrcKeyType::ListIndex(idx));
+        write_param(out, key, param, labels);
+        key.pop();
+    }
+}
+
+fn write_param(out: &mut String, key: &mut PrcKey, param: &ParamKind, labels: &Labels) {
+    match param {
+        ParamKind::Struct(s) => {
+            let _ = writeln!(out, "{} = struct({})", key.to_string_with(labels), s.0.len());
+            write_struct(out, key, s, labels);
+        },
+        ParamKind::List(l) => {
+            let _ = writeln!(out, "{} = list({})", key.to_string_with(labels), l.0.len());
+            write_list(out, key, l, labels);
+        },
+        param => {
+            // write_value only returns None for structs and lists, which are handled above
+            if let Some(value) = write_value(param, labels) {
+                let _ = writeln!(out, "{} = {}", key.to_string_with(labels), value);
+            }
+        }
+    }
+}
+
+/// Flattens an entire [`ParamStruct`] into `key = value` lines, one per param.
+///
+/// Every leaf value carries its type (`1.5f32`, `3u8`, `hash40(fighter_kind_mario)`, ...)
+/// and every struct and list is written as `struct(len)`/`list(len)` before its children,
+/// so [`from_str`] can rebuild the exact same tree.
+pub fn to_string(params: &ParamStruct) -> String {
+    to_string_with(params, Labels::global())
+}
+
+/// Like [`to_string`], with the labels taken from `labels` instead of the global table
+pub fn to_string_with(params: &ParamStruct, labels: &Labels) -> String {
+    let mut out = String::new();
+    write_struct(&mut out, &mut PrcKey::new(), params, labels);
+    out
+}
+
+fn write_tree(out: &mut String, depth: usize, name: &str, param: &ParamKind, labels: &Labels) {
+    let indent = "    ".repeat(depth);
+    match param {
+        ParamKind::Struct(s) => {
+            let _ = writeln!(out, "{}{} = struct({})", indent, name, s.0.len());
+            for (hash, param) in s.0.iter() {
+                let field = PrcKey::from(PrcKeyType::StructField(*hash)).to_string_with(labels);
+                write_tree(out, depth + 1, &field, param, labels);
+            }
+        },
+        ParamKind::List(l) => {
+            let _ = writeln!(out, "{}{} = list({})", indent, name, l.0.len());
+            for (idx, param) in l.0.iter().enumerate() {
+                write_tree(out, depth + 1, &format!("[{}]", idx), param, labels);
+            }
+        },
+        param => {
+            if let Some(value) = write_value(param, labels) {
+                let _ = writeln!(out, "{}{} = {}", indent, name, value);
+            }
+        }
+    }
+}
+
+/// Writes a param and everything in it as an indented tree for reading, one param per line with the
+/// same typed values as [`to_string`]. Struct fields are written by their label and list entries by
+/// their index, under the line of the struct or list they are in. `name` is what the first line is
+/// called, usually the key of the param.
+pub fn to_tree_string(name: &str, param: &ParamKind, labels: &Labels) -> String {
+    let mut out = String::new();
+    write_tree(&mut out, 0, name, param, labels);
+    out
+}
+
+// Walks down to the container that `segments` points at. Struct fields use the last field with a
+// matching hash, since that is the one the most recent lines were written into.
+fn find_node<'a>(root: &'a mut ParamStruct, segments: &[PrcKeyType]) -> Option<Node<'a>> {
+    let mut node = Node::Struct(root);
+    for segment in segments {
+        let param = match (node, segment) {
+            (Node::Struct(s), PrcKeyType::StructField(hash)) => {
+                s.0.iter_mut().rev().find(|(h, _)| h == hash).map(|(_, p)| p)?
+            },
+            (Node::List(l), PrcKeyType::ListIndex(idx)) => l.0.get_mut(*idx)?,
+            _ => return None
+        };
+        node = match param {
+            ParamKind::Struct(s) => Node::Struct(s),
+            ParamKind::List(l) => Node::List(l),
+            _ => return None
+        };
+    }
+    Some(node)
+}
+
+fn parse_container(value: &str, name: &str) -> Option<usize> {
+    value
+        .strip_prefix(name)
+        .and_then(|x| x.strip_prefix("("))
+        .and_then(|x| x.strip_suffix(")"))
+        .and_then(|x| x.parse().ok())
+}
+
+/// Rebuilds a [`ParamStruct`] from the output of [`to_string`].
+///
+/// Lines must come in the order they were written in, and empty lines are skipped.
+pub fn from_str(s: &str) -> Result<ParamStruct> {
+    from_str_with(s, Labels::global())
+}
+
+/// Like [`from_str`], with labels harvested into and errors named by `labels` instead of the global table
+pub fn from_str_with(s: &str, labels: &Labels) -> Result<ParamStruct> {
+    let mut root = ParamStruct(vec![]);
+    let mut lengths = vec![];
+
+    for (line_idx, line) in s.lines().enumerate() {
+        let line_no = line_idx + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (key, value) = split_assignment(line)
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .ok_or_else(|| Error::parse(line_no, 1, "expected `key = value`"))?;
+        let key_str = key;
+        let key = PrcKey::parse_with(key, labels).map_err(|e| e.within(s, key_str))?;
+
+        let (param, expected_len) = if let Some(len) = parse_container(value, "struct") {
+            (ParamKind::Struct(ParamStruct(Vec::with_capacity(len))), Some(len))
+        } else if let Some(len) = parse_container(value, "list") {
+            (ParamKind::List(ParamList(Vec::with_capacity(len))), Some(len))
+        } else {
+            let param = parse_value(value, labels).ok_or_else(|| Error::parse_at(s, value, format!("malformed value `{}`", value)))?;
+            (param, None)
+        };
+
+        let (last, parents) = match key.segments().split_last() {
+            Some(split) => split,
+            None => return Err(Error::parse(line_no, 1, "empty key"))
+        };
+
+        match (find_node(&mut root, parents), last) {
+            (Some(Node::Struct(s)), PrcKeyType::StructField(hash)) => s.0.push((*hash, param)),
+            (Some(Node::List(l)), PrcKeyType::ListIndex(idx)) if *idx == l.0.len() => l.0.push(param),
+            (Some(Node::List(l)), PrcKeyType::ListIndex(idx)) => {
+                return Err(Error::parse_at(s, key_str, format!("expected list index {} but found {}", l.0.len(), idx)));
+            },
+            _ => {
+                // Say what went wrong with the parent when it doesn't exist, such as a misspelled field
+                let parent = key.parent().unwrap_or_default();
+                let reason = match root.get_with(&parent, labels) {
+                    Err(e) if !parent.is_empty() => format!(": {}{}", e.reason(), e.did_you_mean()),
+                    _ => String::new()
+                };
+                return Err(Error::parse_at(s, key_str, format!("`{}` does not point into a declared struct or list{}", key.to_string_with(labels), reason)));
+            }
+        }
+
+        if let Some(len) = expected_len {
+            lengths.push((line_no, key, len));
+        }
+    }
+
+    // Now that every line is in, make sure that no struct or list lost or gained entries
+    for (line_no, key, len) in lengths {
+        let actual = match find_node(&mut root, key.segments()) {
+            Some(Node::Struct(s)) => s.0.len(),
+            Some(Node::List(l)) => l.0.len(),
+            None => continue
+        };
+        if actual != len {
+            return Err(Error::parse(line_no, 1, format!("declared {} entries but found {}", len, actual)));
+        }
+    }
+
+    Ok(root)
+}
+
+/// Writes the dump of a [`ParamStruct`] to the given path. See [`to_string`].
+pub fn save<P: AsRef<Path>>(path: P, params: &ParamStruct) -> Result<()> {
+    save_with(path, params, Labels::global())
+}
+
+/// Like [`save`], with the labels taken from `labels` instead of the global table
+pub fn save_with<P: AsRef<Path>>(path: P, params: &ParamStruct, labels: &Labels) -> Result<()> {
+    std::fs::write(path, to_string_with(params, labels))?;
+    Ok(())
+}
+
+/// Reads a dump written by [`save`] back into a [`ParamStruct`]. See [`from_str`].
+pub fn open<P: AsRef<Path>>(path: P) -> Result<ParamStruct> {
+    open_with(path, Labels::global())
+}
+
+/// Like [`open`], with labels harvested into and errors named by `labels` instead of the global table
+pub fn open_with<P: AsRef<Path>>(path: P, labels: &Labels) -> Result<ParamStruct> {
+    from_str_with(&std::fs::read_to_string(path)?, labels)
+}
+
+#[cfg(test)]
+fn test_params() -> ParamStruct {
+    ParamStruct(vec![
+        (to_hash40("dump_bool"), ParamKind::Bool(true)),
+        (to_hash40("dump_ints"), ParamKind::Struct(ParamStruct(vec![
+            (to_hash40("i8"), ParamKind::I8(-5)),
+            (to_hash40("u8"), ParamKind::U8(5)),
+            (to_hash40("i16"), ParamKind::I16(-300)),
+            (to_hash40("u16"), ParamKind::U16(300)),
+            (to_hash40("i32"), ParamKind::I32(-70000)),
+            (to_hash40("u32"), ParamKind::U32(70000)),
+        ]))),
+        (to_hash40("dump_table"), ParamKind::List(ParamList(vec![
+            ParamKind::Struct(ParamStruct(vec![
+                (to_hash40("dump_float"), ParamKind::Float(-0.1)),
+                (to_hash40("dump_kind"), ParamKind::Hash(to_hash40("dump_unlabeled_kind"))),
+            ])),
+            ParamKind::Struct(ParamStruct(vec![])),
+            ParamKind::List(ParamList(vec![ParamKind::Float(1.0)])),
+        ]))),
+        (to_hash40("dump_empty"), ParamKind::List(ParamList(vec![]))),
+        (to_hash40("dump_str"), ParamKind::Str("say \"hi\"\\\n".to_string())),
+    ])
+}
+
+#[test]
+fn dump_roundtrip_test() {
+    let params = test_params();
+    let dump = to_string(&params);
+    let reloaded = match from_str(&dump) {
+        Ok(params) => params,
+        Err(e) => panic!("Failed to reload dump: {}\n{}", e, dump)
+    };
+
+    assert_eq!(params, reloaded);
+    assert_eq!(dump, to_string(&reloaded));
+}
+
+#[test]
+fn dump_length_mismatch_test() {
+    let dump = to_string(&test_params()).replace("list(3)", "list(4)");
+    assert!(from_str(&dump).is_err());
+}
+
+#[test]
+fn dump_misspelled_parent_test() {
+    crate::hash::add_hashes(vec!["dump_ints"]);
+    let dump = to_string(&test_params()).replacen("dump_ints.", "dump_intz.", 1);
+    match from_str(&dump) {
+        Err(Error::Parse { message, .. }) => assert!(message.ends_with("did you mean `dump_ints`?"), "{}", message),
+        other => panic!("Unexpected result {:?}", other.map(|_| ()))
+    }
+}
+
+#[test]
+fn dump_quoted_label_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["0xdump_label", "dump \"quoted\")"]);
+    let params = ParamStruct(vec![
+        (to_hash40("dump_hex"), ParamKind::Hash(to_hash40("0xdump_label"))),
+        (to_hash40("dump_quoted"), ParamKind::Hash(to_hash40("dump \"quoted\")"))),
+    ]);
+    let dump = to_string_with(&params, &labels);
+    assert!(dump.contains("hash40(\"0xdump_label\")"), "{}", dump);
+    assert_eq!(from_str(&dump).unwrap(), params);
+}
+
+#[test]
+fn tree_string_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["dump_table", "dump_float", "dump_kind"]);
+    let params = test_params();
+    let tree = to_tree_string("dump_table", params.get(&"dump_table".parse().unwrap()).unwrap(), &labels);
+    let expected = format!(
+        "dump_table = list(3)\n    [0] = struct(2)\n        dump_float = -0.1f32\n        dump_kind = hash40({:#x})\n    [1] = struct(0)\n    [2] = list(1)\n        [0] = 1f32\n",
+        to_hash40("dump_unlabeled_kind").0
+    );
+    assert_eq!(tree, expected);
+}
@@ -0,0 +1,194 @@
+use std::fmt;
+
+use prc::{
+    ParamKind,
+    hash40::Hash40
+};
+use thiserror::Error;
+
+use crate::{
+    hash,
+    key::PrcKey
+};
+
+/// Everything that can go wrong while reading, writing or applying patches
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("line {line}, column {column}: {message}")]
+    Parse {
+        line: usize,
+        column: usize,
+        message: String
+    },
+    #[error("The patch is not valid UTF-8.")]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error("Failed to decode binary patch: {0}")]
+    Binary(#[from] bincode::Error),
+    #[error("Failed to decode JSON patch: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Binary patch version {0} is not supported.")]
+    UnsupportedVersion(u8),
+    #[error(transparent)]
+    UnresolvedKey(#[from] ResolveError),
+    #[error(transparent)]
+    LabelCollision(#[from] hash::Collision),
+    #[error("Expected {expected} at `{key}` but found {found}.")]
+    TypeMismatch {
+        key: PrcKey,
+        expected: &'static str,
+        found: &'static str
+    },
+    #[error("`{key}` {change}, which a patch can't hold.")]
+    Unpatchable {
+        key: PrcKey,
+        change: &'static str
+    },
+}
+
+impl Error {
+    pub(crate) fn parse<S: Into<String>>(line: usize, column: usize, message: S) -> Self {
+        Error::Parse {
+            line,
+            column,
+            message: message.into()
+        }
+    }
+
+    // Works out the line and column of `at` inside of `source`, where `at` is a slice of `source`
+    pub(crate) fn parse_at<S: Into<String>>(source: &str, at: &str, message: S) -> Self {
+        let offset = (at.as_ptr() as usize).saturating_sub(source.as_ptr() as usize).min(source.len());
+        let before = &source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().map(|x| x.chars().count()).unwrap_or(0) + 1;
+        Self::parse(line, column, message)
+    }
+
+    // Moves the position of an error that was found while parsing `at` on its own to where `at` is inside of `source`
+    pub(crate) fn within(self, source: &str, at: &str) -> Self {
+        match self {
+            Error::Parse { line: 1, column, message } => {
+                match Self::parse_at(source, at, message) {
+                    Error::Parse { line, column: start, message } => Self::parse(line, start + column - 1, message),
+                    error => error
+                }
+            },
+            error => error
+        }
+    }
+}
+
+/// The name of the type of a param, as used in error messages
+pub(crate) fn type_name(param: &ParamKind) -> &'static str {
+    match param {
+        ParamKind::Bool(_) => "bool",
+        ParamKind::I8(_) => "i8",
+        ParamKind::U8(_) => "u8",
+        ParamKind::I16(_) => "i16",
+        ParamKind::U16(_) => "u16",
+        ParamKind::I32(_) => "i32",
+        ParamKind::U32(_) => "u32",
+        ParamKind::Float(_) => "float",
+        ParamKind::Hash(_) => "hash40",
+        ParamKind::Str(_) => "string",
+        ParamKind::List(_) => "list",
+        ParamKind::Struct(_) => "struct"
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Why a [`PrcKey`] stopped resolving
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolveErrorKind {
+    /// The key has no segments, so it doesn't point at any param
+    EmptyKey,
+    /// The struct has no field with this hash. On its own it is written as hex, [`ResolveError`] names it
+    /// with the labels the key was resolved with.
+    MissingField(Hash40),
+    /// The list is shorter than the index
+    IndexOutOfBounds {
+        index: usize,
+        len: usize
+    },
+    /// The list is shorter than an index counted from its end
+    FromEndOutOfBounds {
+        from_end: usize,
+        len: usize
+    },
+    /// A struct field was used on a param of this type
+    NotAStruct(&'static str),
+    /// A list index was used on a param of this type
+    NotAList(&'static str),
+    /// Something was inserted at a key that already exists
+    AlreadyExists,
+    /// A list pattern like `[*]` was used where a single param is needed
+    NotConcrete,
+    /// A `[field=value]` selector didn't match any entry of the list
+    NoMatch
+}
+
+impl fmt::Display for ResolveErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveErrorKind::EmptyKey => write!(f, "the key is empty"),
+            ResolveErrorKind::MissingField(hash) => write!(f, "there is no field `{:#x}`", hash.0),
+            ResolveErrorKind::IndexOutOfBounds { index, len } => write!(f, "index {} is out of bounds for a list of {}", index, len),
+            ResolveErrorKind::FromEndOutOfBounds { from_end, len } => write!(f, "index -{} is out of bounds for a list of {}", from_end, len),
+            ResolveErrorKind::NotAStruct(found) => write!(f, "expected a struct but found {}", found),
+            ResolveErrorKind::NotAList(found) => write!(f, "expected a list but found {}", found),
+            ResolveErrorKind::AlreadyExists => write!(f, "it already exists"),
+            ResolveErrorKind::NotConcrete => write!(f, "list patterns have to be expanded first"),
+            ResolveErrorKind::NoMatch => write!(f, "no entry of the list matches")
+        }
+    }
+}
+
+/// A [`PrcKey`] that doesn't point at a param, along with how far it got
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("`{}` could not be resolved at `{}`: {}{}", self.key.to_str_named(), self.failed_at().to_str_named(), self.reason(), self.did_you_mean())]
+pub struct ResolveError {
+    /// The whole key that was being resolved, with its fields named by the labels it was resolved with
+    pub key: PrcKey,
+    /// How many segments of the key resolved before it failed
+    pub resolved: usize,
+    pub kind: ResolveErrorKind,
+    /// Labels of fields that do exist where a field was missing and that look like the one in the key
+    pub suggestions: Vec<String>
+}
+
+impl ResolveError {
+    /// The part of the key that did resolve, which is the deepest param that exists
+    pub fn resolved_prefix(&self) -> PrcKey {
+        self.key.iter().take(self.resolved).cloned().collect::<PrcKey>().with_names_of(&self.key)
+    }
+
+    /// The part of the key up to and including the segment that failed
+    pub fn failed_at(&self) -> PrcKey {
+        self.key.iter().take(self.resolved + 1).cloned().collect::<PrcKey>().with_names_of(&self.key)
+    }
+
+    /// The kind of the error, with a missing field called by the name it was typed with in the key
+    pub fn reason(&self) -> String {
+        match &self.kind {
+            ResolveErrorKind::MissingField(hash) => match self.key.typed_name(*hash) {
+                Some(name) => format!("there is no field `{}`", name),
+                None => self.kind.to_string()
+            },
+            kind => kind.to_string()
+        }
+    }
+
+    /// The suggestions formatted as `, did you mean `a` or `b`?`, or nothing if there are none
+    pub fn did_you_mean(&self) -> String {
+        match self.suggestions.split_last() {
+            None => String::new(),
+            Some((last, [])) => format!(", did you mean `{}`?", last),
+            Some((last, rest)) => {
+                let rest: Vec<String> = rest.iter().map(|x| format!("`{}`", x)).collect();
+                format!(", did you mean {} or `{}`?", rest.join(", "), last)
+            }
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::fmt::Write;
+
+use prc::{
+    ParamKind,
+    hash40::Hash40
+};
+
+use crate::{
+    error::{type_name, Error, Result},
+    hash::Labels,
+    key::PrcKey
+};
+
+/// The magic at the start of every binary patch written by this version of the crate
+pub const MAGIC: &[u8; 4] = b"PRCX";
+
+/// The version of the binary patch layout that follows [`MAGIC`]
+pub const BINARY_VERSION: u8 = 1;
+
+/// The version of binary patches that carry the labels they use in front of the patch itself
+pub const LABELED_BINARY_VERSION: u8 = 2;
+
+/// The formats that a [`Diff`](crate::diff::Diff) can be stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `key = value` lines, one per patched param
+    Text,
+    /// bincode prefixed with [`MAGIC`] and [`BINARY_VERSION`], or [`LABELED_BINARY_VERSION`] if labels are embedded
+    Binary,
+    /// Plain bincode with no header, as written by older versions of this crate
+    LegacyBinary,
+    /// The serde representation of the patch as JSON
+    Json,
+    /// A `<prcx>` document with one typed element per patched param
+    Xml
+}
+
+impl Format {
+    /// Guesses the format of a patch from its contents, ignoring the file name entirely.
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(MAGIC) {
+            return Format::Binary;
+        }
+
+        // Text based formats never contain NUL, while the length prefix of legacy bincode almost always does
+        let text = match std::str::from_utf8(data) {
+            Ok(text) if !text.contains('\0') => text,
+            _ => return Format::LegacyBinary
+        };
+
+        match text.trim_start_matches('\u{feff}').trim_start().chars().next() {
+            Some('<') => Format::Xml,
+            Some('{') | Some('[') => Format::Json,
+            _ => Format::Text
+        }
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+fn unescape_xml(s: &str) -> std::result::Result<String, String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find(';')
+            .ok_or_else(|| format!("unterminated entity in `{}`", s))?;
+        let entity = &rest[start + 1..start + end];
+        let c = match entity {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(dec) = entity.strip_prefix('#') {
+                    dec.parse().ok()
+                } else {
+                    None
+                };
+                code.and_then(std::char::from_u32)
+                    .ok_or_else(|| format!("unknown entity `&{};`", entity))?
+            }
+        };
+        unescaped.push(c);
+        rest = &rest[start + end + 1..];
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+fn xml_tag(param: &ParamKind, labels: &Labels) -> Option<(&'static str, String)> {
+    let tag = match param {
+        ParamKind::Struct(_) | ParamKind::List(_) => return None,
+        ParamKind::Bool(bool) => ("bool", bool.to_string()),
+        ParamKind::I8(int) => ("sbyte", int.to_string()),
+        ParamKind::U8(int) => ("byte", int.to_string()),
+        ParamKind::I16(int) => ("short", int.to_string()),
+        ParamKind::U16(int) => ("ushort", int.to_string()),
+        ParamKind::I32(int) => ("int", int.to_string()),
+        ParamKind::U32(int) => ("uint", int.to_string()),
+        ParamKind::Float(float) => ("float", float.to_string()),
+        // Values are trimmed and read as hex when they start with `0x`, so labels that would read back
+        // as a different hash are written as hex instead
+        ParamKind::Hash(hash) => match labels.try_get(*hash) {
+            Some(label) if !label.is_empty() && !label.starts_with("0x") && label.trim() == label => ("hash40", label),
+            _ => ("hash40", format!("{:#x}", hash.0))
+        },
+        ParamKind::Str(string) => ("string", string.clone())
+    };
+    Some(tag)
+}
+
+fn xml_param(tag: &str, value: &str, labels: &Labels) -> Option<ParamKind> {
+    let param = match tag {
+        "bool" => ParamKind::Bool(value.trim().parse().ok()?),
+        "sbyte" => ParamKind::I8(value.trim().parse().ok()?),
+        "byte" => ParamKind::U8(value.trim().parse().ok()?),
+        "short" => ParamKind::I16(value.trim().parse().ok()?),
+        "ushort" => ParamKind::U16(value.trim().parse().ok()?),
+        "int" => ParamKind::I32(value.trim().parse().ok()?),
+        "uint" => ParamKind::U32(value.trim().parse().ok()?),
+        "float" => ParamKind::Float(value.trim().parse().ok()?),
+        "hash40" => {
+            let value = value.trim();
+            if value.starts_with("0x") {
+                ParamKind::Hash(Hash40::from_hex_str(value).ok()?)
+            } else {
+                ParamKind::Hash(labels.hash_label(value))
+            }
+        },
+        "string" => ParamKind::Str(value.to_string()),
+        _ => return None
+    };
+    Some(param)
+}
+
+pub(crate) fn write_xml(entries: &[(PrcKey, ParamKind)], labels: &Labels) -> Result<String> {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<prcx>\n");
+    for (key, param) in entries.iter() {
+        let (tag, value) = xml_tag(param, labels).ok_or_else(|| Error::TypeMismatch {
+            key: key.clone(),
+            expected: "a value",
+            found: type_name(param)
+        })?;
+        let _ = writeln!(out, "  <{} key=\"{}\">{}</{}>", tag, escape_xml(&key.to_string_with(labels)), escape_xml(&value), tag);
+    }
+    out.push_str("</prcx>\n");
+    Ok(out)
+}
+
+struct XmlTag<'a> {
+    name: &'a str,
+    attrs: Vec<(&'a str, String)>,
+    self_closing: bool
+}
+
+// A tiny reader for the flat documents written by write_xml, it doesn't try to be a general purpose XML parser
+struct XmlReader<'a> {
+    source: &'a str,
+    rest: &'a str
+}
+
+impl<'a> XmlReader<'a> {
+    fn error<S: Into<String>>(&self, at: &str, message: S) -> Error {
+        Error::parse_at(self.source, at, message)
+    }
+
+    // Skips whitespace, comments and processing instructions
+    fn skip_misc(&mut self) -> Result<()> {
+        loop {
+            self.rest = self.rest.trim_start_matches('\u{feff}').trim_start();
+            let end = if self.rest.starts_with("<?") {
+                self.rest.find("?>").map(|x| x + 2)
+            } else if self.rest.starts_with("<!--") {
+                self.rest.find("-->").map(|x| x + 3)
+            } else {
+                return Ok(());
+            };
+            let end = end.ok_or_else(|| self.error(self.rest, "unterminated comment or declaration"))?;
+            self.rest = &self.rest[end..];
+        }
+    }
+
+    // Reads `<name attr="value" ...>` or `<name attr="value" ... />`
+    fn open_tag(&mut self) -> Result<XmlTag<'a>> {
+        let start = self.rest;
+        let inner = start
+            .strip_prefix('<')
+            .ok_or_else(|| self.error(start, "expected a tag"))?;
+        let end = inner.find('>').ok_or_else(|| self.error(start, "unterminated tag"))?;
+        self.rest = &inner[end + 1..];
+        let (inner, self_closing) = match inner[..end].strip_suffix('/') {
+            Some(inner) => (inner, true),
+            None => (&inner[..end], false)
+        };
+
+        let inner = inner.trim();
+        let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+        let (name, mut attrs_str) = inner.split_at(name_end);
+        let mut attrs = vec![];
+        loop {
+            attrs_str = attrs_str.trim_start();
+            if attrs_str.is_empty() {
+                break;
+            }
+            let (attr, value) = attrs_str
+                .split_once('=')
+                .ok_or_else(|| self.error(start, format!("malformed attribute in `<{}>`", name)))?;
+            let value = value.trim_start();
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')
+                .ok_or_else(|| self.error(start, format!("unquoted attribute in `<{}>`", name)))?;
+            let value_end = value[1..]
+                .find(quote)
+                .ok_or_else(|| self.error(start, format!("unterminated attribute in `<{}>`", name)))?;
+            let value_str = unescape_xml(&value[1..value_end + 1]).map_err(|e| self.error(start, e))?;
+            attrs.push((attr.trim(), value_str));
+            attrs_str = &value[value_end + 2..];
+        }
+        Ok(XmlTag { name, attrs, self_closing })
+    }
+
+    // Reads the text of the element that was opened at `start`, along with its closing tag
+    fn text_until_close(&mut self, name: &str, start: &str) -> Result<String> {
+        let close = format!("</{}>", name);
+        let end = self.rest.find(&close).ok_or_else(|| self.error(start, format!("`<{}>` was never closed", name)))?;
+        let text = unescape_xml(&self.rest[..end]).map_err(|e| self.error(self.rest, e))?;
+        self.rest = &self.rest[end + close.len()..];
+        Ok(text)
+    }
+}
+
+pub(crate) fn parse_xml(s: &str, labels: &Labels) -> Result<Vec<(PrcKey, ParamKind)>> {
+    let mut reader = XmlReader { source: s, rest: s };
+    reader.skip_misc()?;
+    let root_start = reader.rest;
+    let root = reader.open_tag()?;
+    if root.name != "prcx" {
+        return Err(reader.error(root_start, format!("expected `<prcx>` but found `<{}>`", root.name)));
+    } else if root.self_closing {
+        return Ok(vec![]);
+    }
+
+    let mut entries = vec![];
+    loop {
+        reader.skip_misc()?;
+        if let Some(rest) = reader.rest.strip_prefix("</prcx>") {
+            reader.rest = rest;
+            break;
+        }
+
+        let start = reader.rest;
+        let XmlTag { name: tag, attrs, self_closing } = reader.open_tag()?;
+        let value = if self_closing {
+            String::new()
+        } else {
+            reader.text_until_close(tag, start)?
+        };
+        let key = attrs
+            .into_iter()
+            .find(|(name, _)| *name == "key")
+            .map(|(_, key)| key)
+            .ok_or_else(|| reader.error(start, format!("`<{}>` has no key", tag)))?;
+        let key = PrcKey::parse_with(&key, labels).map_err(|e| reader.error(start, format!("malformed key `{}`: {}", key, e)))?;
+        let param = xml_param(tag, &value, labels).ok_or_else(|| reader.error(start, format!("malformed `<{}>` value `{}`", tag, value)))?;
+        entries.push((key, param));
+    }
+
+    reader.skip_misc()?;
+    if !reader.rest.is_empty() {
+        return Err(reader.error(reader.rest, "unexpected data after `</prcx>`"));
+    }
+    Ok(entries)
+}
@@ -1,33 +1,748 @@
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf}
+};
 
 use parking_lot::RwLock;
-use prc::hash40::{Hash40, to_hash40};
+use prc::{
+    ParamKind,
+    ParamStruct,
+    hash40::{Hash40, to_hash40}
+};
 
+use crate::error::{Error, Result};
 
+/// A table of the labels of hashes.
+///
+/// Most of the crate uses the table returned by [`Labels::global`], which the free functions in this
+/// module fill and read. Separate tables can be passed to the `_with` variants of the calls that write
+/// labels out, such as [`PrcKey::to_string_with`](crate::key::PrcKey::to_string_with), to keep
+/// different label sets apart.
+///
+/// Every label remembers where it came from. When a label is added for a hash that already has a
+/// different one, the table's [`CollisionPolicy`] decides which of them it keeps, and the collision
+/// is logged so that it can be looked at later with [`Labels::collisions`].
+#[derive(Debug, Default)]
+pub struct Labels {
+    table: RwLock<Table>
+}
+
+/// What a [`Labels`] table does when a label is added for a hash that already has a different one.
+///
+/// Labels that were [harvested](LabelSource::Harvested) or [embedded](LabelSource::Embedded) in a patch
+/// never replace one that was added or read from a file, whatever the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Keep the label that was there first
+    KeepFirst,
+    /// Replace it with the new label, which is what the table does unless told otherwise
+    KeepLast,
+    /// Keep the label that was there first, and fail the call that tried to add the new one
+    Error
+}
+
+impl Default for CollisionPolicy {
+    fn default() -> Self {
+        CollisionPolicy::KeepLast
+    }
+}
+
+/// Where a label came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LabelSource {
+    /// Added by a call such as [`Labels::add`]
+    Added,
+    /// Read from a label file. `path` is `None` for labels read from a reader.
+    File {
+        path: Option<PathBuf>,
+        line: usize
+    },
+    /// Harvested from text or string params, see [`Labels::set_harvesting`]
+    Harvested,
+    /// Embedded in a patch, see [`Diff::to_bytes_labeled`](crate::diff::Diff::to_bytes_labeled)
+    Embedded
+}
+
+impl LabelSource {
+    // Labels that someone picked out, rather than ones that were guessed from text or came with a patch
+    fn is_chosen(&self) -> bool {
+        matches!(self, LabelSource::Added | LabelSource::File { .. })
+    }
+}
+
+impl fmt::Display for LabelSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LabelSource::Added => f.write_str("added"),
+            LabelSource::File { path: Some(path), line } => write!(f, "line {} of {}", line, path.display()),
+            LabelSource::File { path: None, line } => write!(f, "line {}", line),
+            LabelSource::Harvested => f.write_str("harvested"),
+            LabelSource::Embedded => f.write_str("embedded in a patch")
+        }
+    }
+}
+
+/// Two different labels for the same hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    pub hash: Hash40,
+    /// The label that the hash already had
+    pub existing: String,
+    pub existing_source: LabelSource,
+    /// The label that was added for it
+    pub label: String,
+    pub source: LabelSource
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` ({}) and `{}` ({}) both hash to {:#x}",
+            self.existing, self.existing_source, self.label, self.source, self.hash.0
+        )
+    }
+}
+
+impl std::error::Error for Collision {}
+
+#[derive(Debug, Default)]
+struct Table {
+    labels: HashMap<Hash40, (String, LabelSource)>,
+    policy: CollisionPolicy,
+    harvesting: bool,
+    collisions: Vec<Collision>
+}
+
+// The most collisions that a table logs before it stops, as harvesting can run into the same ones over and over
+const MAX_COLLISIONS: usize = 1024;
+
+enum Inserted {
+    New,
+    Known,
+    Collided(Collision)
+}
+
+impl Table {
+    // Whether adding `label` would collide, without adding it
+    fn collision(&self, label: &str, source: &LabelSource) -> Option<Collision> {
+        let hash = to_hash40(label);
+        match self.labels.get(&hash) {
+            Some((existing, existing_source)) if existing != label => Some(Collision {
+                hash,
+                existing: existing.clone(),
+                existing_source: existing_source.clone(),
+                label: label.to_string(),
+                source: source.clone()
+            }),
+            _ => None
+        }
+    }
+
+    // Logs a collision unless the same two labels already collided
+    fn log(&mut self, collision: &Collision) {
+        let logged = self
+            .collisions
+            .iter()
+            .any(|logged| logged.hash == collision.hash && logged.existing == collision.existing && logged.label == collision.label);
+        if !logged && self.collisions.len() < MAX_COLLISIONS {
+            self.collisions.push(collision.clone());
+        }
+    }
+
+    fn insert(&mut self, label: &str, source: LabelSource) -> Inserted {
+        if let Some(collision) = self.collision(label, &source) {
+            let keeps_existing = collision.existing_source.is_chosen() && !source.is_chosen();
+            if self.policy == CollisionPolicy::KeepLast && !keeps_existing {
+                self.labels.insert(collision.hash, (label.to_string(), source));
+            }
+            self.log(&collision);
+            return Inserted::Collided(collision);
+        }
+        match self.labels.entry(to_hash40(label)) {
+            Entry::Occupied(_) => Inserted::Known,
+            Entry::Vacant(entry) => {
+                entry.insert((label.to_string(), source));
+                Inserted::New
+            }
+        }
+    }
+}
 
 lazy_static::lazy_static! {
-    static ref HASHES: RwLock<HashMap<Hash40, String>> = RwLock::new(HashMap::new());
+    static ref GLOBAL: Labels = Labels::new();
+}
+
+impl Labels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The table that is used when no other one is given
+    pub fn global() -> &'static Labels {
+        &GLOBAL
+    }
+
+    pub fn with_policy(policy: CollisionPolicy) -> Self {
+        let labels = Self::default();
+        labels.set_policy(policy);
+        labels
+    }
+
+    pub fn policy(&self) -> CollisionPolicy {
+        self.table.read().policy
+    }
+
+    pub fn set_policy(&self, policy: CollisionPolicy) {
+        self.table.write().policy = policy;
+    }
+
+    /// Turns label harvesting on or off, it is off to begin with. While it is on, every label that is read
+    /// from text with this table is added to it: field names in keys, and hash values in text, XML and dumped
+    /// patches. Those would otherwise only be kept as their hash.
+    pub fn set_harvesting(&self, enabled: bool) {
+        self.table.write().harvesting = enabled;
+    }
+
+    pub fn is_harvesting(&self) -> bool {
+        self.table.read().harvesting
+    }
+
+    // Hashes a label that was read from text, keeping the label if harvesting is on
+    pub(crate) fn hash_label(&self, label: &str) -> Hash40 {
+        // Only lock for writing when harvesting, so parsing with a shared table doesn't serialize
+        if self.is_harvesting() {
+            let mut table = self.table.write();
+            if table.harvesting {
+                table.insert(label, LabelSource::Harvested);
+            }
+        }
+        to_hash40(label)
+    }
+
+    /// Adds a label. Collisions are logged, see [`try_add`](Self::try_add) to find out about them.
+    pub fn add<S: AsRef<str>>(&self, label: S) {
+        let _ = self.try_add(label, LabelSource::Added);
+    }
+
+    pub fn add_all<I: IntoIterator<Item = S>, S: AsRef<str>>(&self, labels: I) {
+        let mut table = self.table.write();
+        for label in labels {
+            table.insert(label.as_ref(), LabelSource::Added);
+        }
+    }
+
+    /// Adds a label from `source`. Fails if the hash already has a different label and the policy is
+    /// [`CollisionPolicy::Error`], for the other policies the collision is only logged.
+    pub fn try_add<S: AsRef<str>>(&self, label: S, source: LabelSource) -> std::result::Result<(), Collision> {
+        let mut table = self.table.write();
+        match table.insert(label.as_ref(), source) {
+            Inserted::Collided(collision) if table.policy == CollisionPolicy::Error => Err(collision),
+            _ => Ok(())
+        }
+    }
+
+    pub fn try_get(&self, hash: Hash40) -> Option<String> {
+        self.table.read().labels.get(&hash).map(|(label, _)| label.clone())
+    }
+
+    /// The label of a hash, or the hash in hex if it has none
+    pub fn get(&self, hash: Hash40) -> String {
+        match self.try_get(hash) {
+            Some(label) => label,
+            None => format!("{:#x}", hash.0)
+        }
+    }
+
+    /// Where the label of a hash came from
+    pub fn source(&self, hash: Hash40) -> Option<LabelSource> {
+        self.table.read().labels.get(&hash).map(|(_, source)| source.clone())
+    }
+
+    pub fn contains(&self, hash: Hash40) -> bool {
+        self.table.read().labels.contains_key(&hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.read().labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.read().labels.is_empty()
+    }
+
+    /// Every hash and label in the table, in no particular order
+    pub fn entries(&self) -> Vec<(Hash40, String)> {
+        self.table.read().labels.iter().map(|(hash, (label, _))| (*hash, label.clone())).collect()
+    }
+
+    /// Every collision since the table was made or the log was last taken, oldest first. The same two
+    /// labels are only logged once, and only the first 1024 collisions are kept.
+    pub fn collisions(&self) -> Vec<Collision> {
+        self.table.read().collisions.clone()
+    }
+
+    /// Empties the collision log and returns what was in it
+    pub fn take_collisions(&self) -> Vec<Collision> {
+        std::mem::take(&mut self.table.write().collisions)
+    }
+
+    /// See [`closest_labels`]
+    pub fn closest<I: IntoIterator<Item = Hash40>>(&self, name: &str, candidates: I) -> Vec<String> {
+        let max_distance = (name.chars().count() / 4).max(1);
+        let mut found: Vec<(usize, String)> = candidates
+            .into_iter()
+            .filter_map(|hash| self.try_get(hash))
+            .map(|label| (edit_distance(name, &label), label))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .collect();
+        found.sort();
+        found.dedup();
+        found.into_iter().take(3).map(|(_, label)| label).collect()
+    }
+
+    /// Finds labels by name, see [`LabelSearch`] for how each kind of search matches and orders them.
+    /// Case is ignored.
+    pub fn search(&self, query: &str, search: LabelSearch) -> Vec<(Hash40, String)> {
+        let query = query.to_lowercase();
+        let table = self.table.read();
+        let mut found: Vec<(usize, Hash40, String)> = table
+            .labels
+            .iter()
+            .filter_map(|(hash, (label, _))| {
+                let lower = label.to_lowercase();
+                let rank = match search {
+                    LabelSearch::Prefix if lower.starts_with(&query) => lower.len(),
+                    LabelSearch::Substring => lower.find(&query)?,
+                    LabelSearch::Fuzzy => {
+                        let distance = edit_distance(&query, &lower);
+                        if distance > (query.chars().count() / 4).max(1) && !is_subsequence(&query, &lower) {
+                            return None;
+                        }
+                        distance
+                    },
+                    _ => return None
+                };
+                Some((rank, *hash, label.clone()))
+            })
+            .collect();
+        found.sort_by(|(a_rank, _, a), (b_rank, _, b)| a_rank.cmp(b_rank).then(a.len().cmp(&b.len())).then(a.cmp(b)));
+        found.into_iter().map(|(_, hash, label)| (hash, label)).collect()
+    }
+
+    /// Adds every non-empty [`ParamKind::Str`] in `params` as a label, since many of them name hashes
+    /// used elsewhere. Returns how many of them weren't already in the table.
+    pub fn harvest_strings(&self, params: &ParamStruct) -> usize {
+        fn walk(param: &ParamKind, found: &mut Vec<String>) {
+            match param {
+                ParamKind::Struct(s) => s.0.iter().for_each(|(_, param)| walk(param, found)),
+                ParamKind::List(l) => l.0.iter().for_each(|param| walk(param, found)),
+                ParamKind::Str(string) if !string.is_empty() => found.push(string.clone()),
+                _ => {}
+            }
+        }
+
+        let mut found = vec![];
+        params.0.iter().for_each(|(_, param)| walk(param, &mut found));
+        let mut table = self.table.write();
+        found
+            .into_iter()
+            .filter(|label| matches!(table.insert(label, LabelSource::Harvested), Inserted::New))
+            .count()
+    }
+
+    /// See [`read_labels`]
+    pub fn read<R: Read>(&self, reader: R, format: LabelFormat) -> Result<LabelReport> {
+        self.read_from(reader, format, None)
+    }
+
+    fn read_from<R: Read>(&self, reader: R, format: LabelFormat, path: Option<&Path>) -> Result<LabelReport> {
+        let mut report = LabelReport::default();
+        let mut labels = vec![];
+        for (idx, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            let line = line.trim_start_matches('\u{feff}').trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let issue = |kind| LabelIssue { line: idx + 1, kind };
+            let label = match format {
+                LabelFormat::List => line,
+                LabelFormat::Csv => {
+                    let listed = line
+                        .split_once(',')
+                        .filter(|(hash, label)| hash.trim().starts_with("0x") && !label.trim().is_empty())
+                        .and_then(|(hash, label)| Some((Hash40::from_hex_str(hash.trim()).ok()?, label.trim())));
+                    match listed {
+                        Some((hash, label)) => {
+                            if to_hash40(label) != hash {
+                                report.issues.push(issue(LabelIssueKind::Mismatch { listed: hash, label: label.to_string() }));
+                            }
+                            label
+                        },
+                        None => {
+                            report.issues.push(issue(LabelIssueKind::Malformed(line.to_string())));
+                            continue;
+                        }
+                    }
+                }
+            };
+            labels.push((label.to_string(), LabelSource::File { path: path.map(Path::to_path_buf), line: idx + 1 }));
+        }
+
+        let mut table = self.table.write();
+        // Nothing is added from a file that collides under the error policy, including with itself
+        if table.policy == CollisionPolicy::Error {
+            let mut pending = HashMap::new();
+            for (label, source) in labels.iter() {
+                if let Some(collision) = table.collision(label, source) {
+                    return Err(Error::LabelCollision(collision));
+                }
+                if let Some((existing, existing_source)) = pending.insert(to_hash40(label), (label, source)) {
+                    if existing != label {
+                        return Err(Error::LabelCollision(Collision {
+                            hash: to_hash40(label),
+                            existing: existing.clone(),
+                            existing_source: existing_source.clone(),
+                            label: label.clone(),
+                            source: source.clone()
+                        }));
+                    }
+                }
+            }
+        }
+
+        report.added = labels.len();
+        for (label, source) in labels {
+            if let Inserted::Collided(collision) = table.insert(&label, source) {
+                report.collisions.push(collision);
+            }
+        }
+        Ok(report)
+    }
+
+    /// See [`load_labels`]
+    pub fn load<P: AsRef<Path>>(&self, path: P, format: LabelFormat) -> Result<LabelReport> {
+        self.read_from(File::open(path.as_ref())?, format, Some(path.as_ref()))
+    }
+
+    /// See [`write_labels`]
+    pub fn write<W: Write>(&self, writer: W, format: LabelFormat) -> Result<()> {
+        let mut labels = self.entries();
+        let mut writer = BufWriter::new(writer);
+        match format {
+            LabelFormat::Csv => {
+                labels.sort_by_key(|(hash, _)| hash.0);
+                for (hash, label) in labels {
+                    writeln!(writer, "{:#012x},{}", hash.0, label)?;
+                }
+            },
+            LabelFormat::List => {
+                labels.sort_by(|(_, a), (_, b)| a.cmp(b));
+                for (_, label) in labels {
+                    writeln!(writer, "{}", label)?;
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// See [`save_labels`]
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: LabelFormat) -> Result<()> {
+        self.write(File::create(path)?, format)
+    }
 }
 
 pub fn add_hash<S: AsRef<str>>(string: S) {
-    let s = string.as_ref();
-    HASHES.write().insert(to_hash40(s), s.to_string());
+    GLOBAL.add(string);
 }
 
 pub fn add_hashes(strings: Vec<&str>) {
-    let mut hashes = HASHES.write();
-    for s in strings {
-        hashes.insert(to_hash40(s), s.to_string());
-    }
+    GLOBAL.add_all(strings);
 }
 
 pub fn try_get(hash: Hash40) -> Option<String> {
-    HASHES.read().get(&hash).map(|x| x.clone())
+    GLOBAL.try_get(hash)
 }
 
 pub fn get(hash: Hash40) -> String {
-    match try_get(hash) {
-        Some(s) => s.to_string(),
-        None => format!("{:#x}", hash.0)
+    GLOBAL.get(hash)
+}
+
+/// Turns label harvesting on or off for the global table. See [`Labels::set_harvesting`].
+pub fn set_harvesting(enabled: bool) {
+    GLOBAL.set_harvesting(enabled);
+}
+
+pub fn is_harvesting() -> bool {
+    GLOBAL.is_harvesting()
+}
+
+/// Adds the strings in `params` to the global table. See [`Labels::harvest_strings`].
+pub fn harvest_strings(params: &ParamStruct) -> usize {
+    GLOBAL.harvest_strings(params)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let next = (diagonal + (a != *b) as usize).min(row[j] + 1).min(row[j + 1] + 1);
+            diagonal = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|other| other == c))
+}
+
+/// The ways [`Labels::search`] can find labels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelSearch {
+    /// Labels that start with the query, shortest first
+    Prefix,
+    /// Labels that have the query in them, earliest match first
+    Substring,
+    /// Labels that have every character of the query in order, such as `jpspd` for `jump_speed`,
+    /// or that are a typo or two away from it. Closest by edit distance first.
+    Fuzzy
+}
+
+/// Finds labels in the global table by name. See [`Labels::search`].
+pub fn search_labels(query: &str, search: LabelSearch) -> Vec<(Hash40, String)> {
+    GLOBAL.search(query, search)
+}
+
+/// The labels of `candidates` that are closest to `name` by edit distance, best first.
+/// Only labels within a quarter of the length of `name` are suggested, and at most three of them.
+pub fn closest_labels<I: IntoIterator<Item = Hash40>>(name: &str, candidates: I) -> Vec<String> {
+    GLOBAL.closest(name, candidates)
+}
+
+/// The layouts of label files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelFormat {
+    /// `0xhash,label` lines, like ParamLabels.csv
+    Csv,
+    /// One label per line
+    List
+}
+
+/// Why a line of a label file was left out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LabelIssueKind {
+    /// The line isn't a label in the format that was asked for
+    Malformed(String),
+    /// The label doesn't hash to the hash it was listed with. It is still added under its own hash.
+    Mismatch {
+        listed: Hash40,
+        label: String
+    }
+}
+
+/// A line of a label file that didn't load the way it was written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelIssue {
+    pub line: usize,
+    pub kind: LabelIssueKind
+}
+
+impl fmt::Display for LabelIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            LabelIssueKind::Malformed(line) => write!(f, "line {}: malformed label line `{}`", self.line, line),
+            LabelIssueKind::Mismatch { listed, label } => {
+                write!(f, "line {}: `{}` hashes to {:#x}, not {:#x}", self.line, label, to_hash40(label).0, listed.0)
+            }
+        }
     }
-}
\ No newline at end of file
+}
+
+/// What loading a label file did
+#[derive(Debug, Default)]
+pub struct LabelReport {
+    /// How many labels were added, including ones that were already known
+    pub added: usize,
+    pub issues: Vec<LabelIssue>,
+    /// Labels in the file that collided with ones the table already had, or with earlier lines
+    pub collisions: Vec<Collision>
+}
+
+/// Adds every label that `reader` holds in the given format. Lines that don't fit are listed in the report
+/// instead of failing the whole file, and blank lines are skipped.
+pub fn read_labels<R: Read>(reader: R, format: LabelFormat) -> Result<LabelReport> {
+    GLOBAL.read(reader, format)
+}
+
+/// Adds every label in the file at `path`. See [`read_labels`].
+pub fn load_labels<P: AsRef<Path>>(path: P, format: LabelFormat) -> Result<LabelReport> {
+    GLOBAL.load(path, format)
+}
+
+/// Writes every known label in the given format, sorted by hash for CSV and by label for lists
+pub fn write_labels<W: Write>(writer: W, format: LabelFormat) -> Result<()> {
+    GLOBAL.write(writer, format)
+}
+
+/// Writes every known label to the file at `path`. See [`write_labels`].
+pub fn save_labels<P: AsRef<Path>>(path: P, format: LabelFormat) -> Result<()> {
+    GLOBAL.save(path, format)
+}
+
+#[test]
+fn closest_labels_test() {
+    add_hashes(vec!["suggest_walk_speed", "suggest_walk_accel", "suggest_run_speed"]);
+    let candidates = vec![to_hash40("suggest_walk_speed"), to_hash40("suggest_walk_accel"), to_hash40("suggest_run_speed"), to_hash40("suggest_unlabeled")];
+
+    let found = closest_labels("suggest_walk_sped", candidates.clone());
+    assert_eq!(found[0], "suggest_walk_speed");
+    assert!(!found.contains(&"suggest_run_speed".to_string()));
+    assert_eq!(closest_labels("suggest_run_sped", candidates.clone()), vec!["suggest_run_speed"]);
+    assert_eq!(closest_labels("suggest_jump", candidates), Vec::<String>::new());
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+}
+
+#[test]
+fn label_file_test() {
+    let csv = format!(
+        "{:#012x},label_file_csv\r\n\nnot a label line\n{:#x},label_file_wrong\n0xzz,label_file_bad_hash\n",
+        to_hash40("label_file_csv").0,
+        to_hash40("label_file_other").0
+    );
+    let report = read_labels(csv.as_bytes(), LabelFormat::Csv).unwrap();
+    assert_eq!(report.added, 2);
+    assert_eq!(report.issues, vec![
+        LabelIssue { line: 3, kind: LabelIssueKind::Malformed("not a label line".to_string()) },
+        LabelIssue { line: 4, kind: LabelIssueKind::Mismatch { listed: to_hash40("label_file_other"), label: "label_file_wrong".to_string() } },
+        LabelIssue { line: 5, kind: LabelIssueKind::Malformed("0xzz,label_file_bad_hash".to_string()) },
+    ]);
+    assert_eq!(try_get(to_hash40("label_file_csv")), Some("label_file_csv".to_string()));
+    assert_eq!(try_get(to_hash40("label_file_wrong")), Some("label_file_wrong".to_string()));
+    assert_eq!(try_get(to_hash40("label_file_other")), None);
+
+    let report = read_labels("label_file_list_a\n  label_file_list_b  \n".as_bytes(), LabelFormat::List).unwrap();
+    assert_eq!((report.added, report.issues.len()), (2, 0));
+
+    let mut out = vec![];
+    write_labels(&mut out, LabelFormat::Csv).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.lines().any(|line| line == format!("{:#012x},label_file_list_b", to_hash40("label_file_list_b").0)));
+    let reloaded = read_labels(out.as_bytes(), LabelFormat::Csv).unwrap();
+    assert!(reloaded.issues.is_empty());
+}
+
+#[test]
+fn separate_labels_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["separate_labels_only"]);
+    let hash = to_hash40("separate_labels_only");
+    assert_eq!(labels.get(hash), "separate_labels_only");
+    assert_eq!(try_get(hash), None);
+    assert_eq!(labels.len(), 1);
+
+    let mut out = vec![];
+    labels.write(&mut out, LabelFormat::List).unwrap();
+    assert_eq!(out, b"separate_labels_only\n");
+}
+
+#[test]
+fn harvest_test() {
+    use prc::ParamList;
+
+    let labels = Labels::new();
+    crate::key::PrcKey::parse_with("harvest_off_field", &labels).unwrap();
+    assert_eq!(labels.try_get(to_hash40("harvest_off_field")), None);
+
+    labels.set_harvesting(true);
+    crate::key::PrcKey::parse_with("harvest_table[0].\"harvest quoted\"", &labels).unwrap();
+    crate::diff::Diff::from_bytes_as_with(b"harvest_kind = harvest_value\n", crate::format::Format::Text, &labels).unwrap();
+    labels.set_harvesting(false);
+    for label in ["harvest_table", "harvest quoted", "harvest_kind", "harvest_value"].iter() {
+        assert_eq!(labels.try_get(to_hash40(label)).as_deref(), Some(*label));
+        assert_eq!(try_get(to_hash40(label)), None);
+    }
+
+    let labels = Labels::new();
+    let params = ParamStruct(vec![
+        (to_hash40("harvest_name"), ParamKind::Str("harvest_string".to_string())),
+        (to_hash40("harvest_list"), ParamKind::List(ParamList(vec![ParamKind::Str("harvest_string".to_string()), ParamKind::Str(String::new())])))
+    ]);
+    assert_eq!(labels.harvest_strings(&params), 1);
+    assert_eq!(labels.try_get(to_hash40("harvest_string")), Some("harvest_string".to_string()));
+    assert_eq!(labels.harvest_strings(&params), 0);
+}
+
+#[test]
+fn collision_test() {
+    // `plumless` and `buckeroo` have the same crc32 and length, so the same hash40
+    let hash = to_hash40("plumless");
+    assert_eq!(hash, to_hash40("buckeroo"));
+
+    let labels = Labels::new();
+    labels.add("plumless");
+    labels.add("buckeroo");
+    assert_eq!(labels.get(hash), "buckeroo");
+    assert_eq!(labels.collisions().len(), 1);
+    assert_eq!(labels.take_collisions()[0].existing, "plumless");
+    assert!(labels.collisions().is_empty());
+
+    let labels = Labels::with_policy(CollisionPolicy::KeepFirst);
+    labels.add_all(vec!["plumless", "plumless", "buckeroo"]);
+    assert_eq!(labels.get(hash), "plumless");
+    assert_eq!(labels.collisions().len(), 1);
+
+    // Harvested and embedded labels don't replace chosen ones, and repeats are only logged once
+    let labels = Labels::new();
+    labels.add("plumless");
+    for _ in 0..3 {
+        labels.try_add("buckeroo", LabelSource::Harvested).unwrap();
+        labels.try_add("buckeroo", LabelSource::Embedded).unwrap();
+    }
+    assert_eq!(labels.get(hash), "plumless");
+    assert_eq!(labels.collisions().len(), 1);
+    labels.try_add("buckeroo", LabelSource::Added).unwrap();
+    assert_eq!(labels.get(hash), "buckeroo");
+    assert_eq!(CollisionPolicy::default(), CollisionPolicy::KeepLast);
+
+    let labels = Labels::with_policy(CollisionPolicy::Error);
+    labels.add("plumless");
+    let collision = labels.try_add("buckeroo", LabelSource::Harvested).unwrap_err();
+    assert_eq!((collision.existing_source, collision.source), (LabelSource::Added, LabelSource::Harvested));
+    assert_eq!(labels.get(hash), "plumless");
+
+    let error = labels.read("collision_file\nbuckeroo\n".as_bytes(), LabelFormat::List).unwrap_err();
+    assert!(error.to_string().contains("`buckeroo` (line 2)"), "{}", error);
+    assert!(!labels.contains(to_hash40("collision_file")));
+
+    let labels = Labels::new();
+    let report = labels.read("plumless\nbuckeroo\n".as_bytes(), LabelFormat::List).unwrap();
+    assert_eq!(report.collisions.len(), 1);
+    assert_eq!(labels.source(hash), Some(LabelSource::File { path: None, line: 2 }));
+}
+
+#[test]
+fn search_labels_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["jump_speed_x", "jump_speed_x_max", "air_jump_speed", "walk_speed", "Jump_Count"]);
+    let names = |found: Vec<(Hash40, String)>| found.into_iter().map(|(_, label)| label).collect::<Vec<_>>();
+
+    assert_eq!(names(labels.search("jump_", LabelSearch::Prefix)), vec!["Jump_Count", "jump_speed_x", "jump_speed_x_max"]);
+    assert_eq!(names(labels.search("speed", LabelSearch::Substring)), vec!["walk_speed", "jump_speed_x", "jump_speed_x_max", "air_jump_speed"]);
+    assert_eq!(names(labels.search("jmp_sped_x", LabelSearch::Fuzzy)), vec!["jump_speed_x", "jump_speed_x_max"]);
+    assert_eq!(names(labels.search("wlkspd", LabelSearch::Fuzzy)), vec!["walk_speed"]);
+    let (hash, _) = &labels.search("walk_speed", LabelSearch::Prefix)[0];
+    assert_eq!(*hash, to_hash40("walk_speed"));
+    assert!(labels.search("run", LabelSearch::Substring).is_empty());
+}
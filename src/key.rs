@@ -1,131 +1,702 @@
-use std::{str::FromStr, cmp::Ordering};
-use serde::{Serialize, Deserialize};
-use thiserror::Error;
+use std::{str::FromStr, fmt, slice, cmp::Ordering, hash::{Hash, Hasher}, iter::FromIterator};
+use serde::{
+    Serialize,
+    Serializer,
+    Deserialize,
+    Deserializer,
+    de::{self, MapAccess, Visitor, value::MapAccessDeserializer},
+    ser::{SerializeStruct, Error as _}
+};
 
-use prc::{hash40::{Hash40, to_hash40}};
+use prc::{
+    ParamKind,
+    hash40::{Hash40, to_hash40}
+};
 
-use crate::hash;
+use crate::{
+    error::Error,
+    hash::Labels
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The value that a `[field=value]` selector compares a field of each list entry against
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum SelectorValue {
+    /// A label or `0x` hash, which matches hash40 params
+    Hash(Hash40),
+    /// A number, which matches params of any integer type with the same value
+    Int(i64),
+    /// A quoted string, which matches string params
+    Str(String)
+}
+
+impl SelectorValue {
+    /// Whether a param has this value
+    pub fn matches(&self, param: &ParamKind) -> bool {
+        match (self, param) {
+            (SelectorValue::Hash(hash), ParamKind::Hash(other)) => hash == other,
+            (SelectorValue::Str(string), ParamKind::Str(other)) => string == other,
+            (SelectorValue::Int(int), param) => {
+                let other = match param {
+                    ParamKind::I8(x) => *x as i64,
+                    ParamKind::U8(x) => *x as i64,
+                    ParamKind::I16(x) => *x as i64,
+                    ParamKind::U16(x) => *x as i64,
+                    ParamKind::I32(x) => *x as i64,
+                    ParamKind::U32(x) => *x as i64,
+                    _ => return false
+                };
+                *int == other
+            },
+            _ => false
+        }
+    }
+
+    fn to_str(&self, hashed: bool, label: &dyn Fn(Hash40) -> Option<String>) -> String {
+        match self {
+            // A quoted value is a string, so labels that would need quotes are written as hex instead
+            SelectorValue::Hash(hash) => match label(*hash) {
+                Some(label) if !hashed && !needs_quotes(&label) => label,
+                _ => format!("{:#x}", hash.0)
+            },
+            SelectorValue::Int(int) => int.to_string(),
+            SelectorValue::Str(string) => quote(string)
+        }
+    }
+}
+
+/// One step of a [`PrcKey`], either into a field of a struct or into an entry of a list
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum PrcKeyType {
     StructField(Hash40),
-    ListIndex(usize)
+    ListIndex(usize),
+    /// `[*]`, every entry of the list
+    ListAll,
+    /// `[start..end]`, the entries from `start` up to but not including `end`, or up to the end of the list
+    ListRange {
+        start: usize,
+        end: Option<usize>
+    },
+    /// `[field=value]`, every entry of the list that is a struct whose `field` matches `value`
+    ListSelect {
+        field: Hash40,
+        value: SelectorValue
+    },
+    /// `[-n]`, the entry `n` places from the end of the list, so `[-1]` is the last one
+    ListFromEnd(usize),
+    /// `[+]`, a new entry after the end of the list. Patches append their value to the list with it.
+    ListAppend
+}
+
+impl PrcKeyType {
+    /// Whether this segment points at exactly one param, rather than being a pattern that has to be expanded
+    pub fn is_concrete(&self) -> bool {
+        matches!(self, PrcKeyType::StructField(_) | PrcKeyType::ListIndex(_) | PrcKeyType::ListFromEnd(_))
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A path to a param inside of a [`ParamStruct`](prc::ParamStruct), such as `fighter_param_table[0].walk_accel_mul`.
+///
+/// Keys compare in tree order: a key sorts right before everything underneath it, list indices sort
+/// numerically and struct fields sort by their hash.
+#[derive(Debug, Default, Clone)]
 pub struct PrcKey {
-    pub ty: PrcKeyType,
-    pub next: Option<Box<PrcKey>>
+    segments: Vec<PrcKeyType>,
+    // The names that struct fields were typed with when the key was parsed or built. They are only used
+    // to say what a key that doesn't resolve meant, and take no part in comparing keys.
+    names: Vec<(Hash40, String)>
 }
 
-#[derive(Error, Debug)]
-pub enum PrcKeyError {
-    #[error("The PRC key was malformed.")]
-    Malformed,
+impl PartialEq for PrcKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.segments == other.segments
+    }
+}
+
+impl Eq for PrcKey {}
+
+impl Hash for PrcKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.segments.hash(state);
+    }
+}
+
+impl PartialOrd for PrcKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PrcKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.segments.cmp(&other.segments)
+    }
+}
+
+/// Kept so that code written against the old key error keeps compiling
+#[deprecated(note = "Use prcx::Error instead")]
+pub type PrcKeyError = Error;
+
+/// Builds a [`PrcKey`] one segment at a time, starting from [`PrcKey::field`] or [`PrcKey::builder`]
+///
+/// `PrcKey::field("fighter_param_table").index(0).field("walk_accel_mul").build()`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PrcKeyBuilder {
+    key: PrcKey
+}
+
+impl PrcKeyBuilder {
+    /// A struct field by its label
+    pub fn field<S: AsRef<str>>(mut self, label: S) -> Self {
+        let hash = to_hash40(label.as_ref());
+        self.key.add_name(hash, label.as_ref());
+        self.segment(PrcKeyType::StructField(hash))
+    }
+
+    /// A struct field by its hash, for fields without a known label
+    pub fn hash(self, hash: Hash40) -> Self {
+        self.segment(PrcKeyType::StructField(hash))
+    }
+
+    pub fn index(self, idx: usize) -> Self {
+        self.segment(PrcKeyType::ListIndex(idx))
+    }
+
+    /// `[-n]`, counting back from the end of the list
+    pub fn from_end(self, from_end: usize) -> Self {
+        self.segment(PrcKeyType::ListFromEnd(from_end))
+    }
+
+    /// `[*]`
+    pub fn all(self) -> Self {
+        self.segment(PrcKeyType::ListAll)
+    }
+
+    /// `[+]`
+    pub fn append(self) -> Self {
+        self.segment(PrcKeyType::ListAppend)
+    }
+
+    pub fn segment(mut self, segment: PrcKeyType) -> Self {
+        self.key.push(segment);
+        self
+    }
+
+    pub fn build(self) -> PrcKey {
+        self.key
+    }
+}
+
+impl From<PrcKeyBuilder> for PrcKey {
+    fn from(builder: PrcKeyBuilder) -> Self {
+        builder.key
+    }
 }
 
 impl PrcKey {
-    const INVALID_STR_INDEX: usize = 0x300;
+    /// Creates an empty key, which points at the root struct itself
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    fn get_key(index: &str) -> Option<Self> {
-        // If the key starts with "." we want to ignore it, i.e. for ".some_struct.some_key"
-        // we only want to worry about "some_struct.some_key"
-        let index = if index.starts_with(".") {
-            index.split_at(1).1
-        } else {
-            index
+    /// Starts building a key with a struct field, see [`PrcKeyBuilder`]
+    pub fn field<S: AsRef<str>>(label: S) -> PrcKeyBuilder {
+        Self::builder().field(label)
+    }
+
+    /// Starts building a key from nothing, see [`PrcKeyBuilder`]
+    pub fn builder() -> PrcKeyBuilder {
+        PrcKeyBuilder::default()
+    }
+
+    pub fn segments(&self) -> &[PrcKeyType] {
+        &self.segments
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, PrcKeyType> {
+        self.segments.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn first(&self) -> Option<&PrcKeyType> {
+        self.segments.first()
+    }
+
+    pub fn last(&self) -> Option<&PrcKeyType> {
+        self.segments.last()
+    }
+
+    /// Whether every segment points at exactly one param. Keys with `[*]` or ranges in them have to be
+    /// expanded against the params first, see [`ParamAccess::expand`](crate::access::ParamAccess::expand).
+    pub fn is_concrete(&self) -> bool {
+        self.iter().all(PrcKeyType::is_concrete)
+    }
+
+    pub fn push(&mut self, segment: PrcKeyType) {
+        self.segments.push(segment);
+    }
+
+    pub fn pop(&mut self) -> Option<PrcKeyType> {
+        self.segments.pop()
+    }
+
+    /// The key of the struct or list that contains this one, or `None` for the empty key
+    pub fn parent(&self) -> Option<PrcKey> {
+        self.segments
+            .split_last()
+            .map(|(_, parent)| parent.iter().cloned().collect::<PrcKey>().with_names_of(self))
+    }
+
+    /// Whether `prefix` is this key or one of its ancestors
+    pub fn starts_with(&self, prefix: &PrcKey) -> bool {
+        self.segments.starts_with(&prefix.segments)
+    }
+
+    /// The rest of this key relative to `prefix`, or `None` when `prefix` isn't an ancestor of it
+    pub fn strip_prefix(&self, prefix: &PrcKey) -> Option<PrcKey> {
+        self.segments
+            .strip_prefix(prefix.segments.as_slice())
+            .map(|rest| rest.iter().cloned().collect::<PrcKey>().with_names_of(self))
+    }
+
+    /// A new key that continues this one with every segment of `other`
+    pub fn join(&self, other: &PrcKey) -> PrcKey {
+        self.iter().chain(other.iter()).cloned().collect::<PrcKey>().with_names_of(self).with_names_of(other)
+    }
+
+    pub(crate) fn add_name(&mut self, hash: Hash40, name: &str) {
+        if !self.names.iter().any(|(other, _)| *other == hash) {
+            self.names.push((hash, name.to_string()));
+        }
+    }
+
+    // The name that a struct field of the key was typed with, if it was parsed from text or built by label
+    pub(crate) fn typed_name(&self, hash: Hash40) -> Option<&str> {
+        self.names.iter().find(|(other, _)| *other == hash).map(|(_, name)| name.as_str())
+    }
+
+    // Keeps the typed names of `other` as well, for keys that are made out of the segments of another one
+    pub(crate) fn with_names_of(mut self, other: &PrcKey) -> Self {
+        for (hash, name) in other.names.iter() {
+            self.add_name(*hash, name);
+        }
+        self
+    }
+
+    // Names every field and selector of the key that `labels` has a label for and that wasn't typed by name
+    pub(crate) fn with_labels_of(mut self, labels: &Labels) -> Self {
+        let hashes: Vec<Hash40> = self.segments
+            .iter()
+            .flat_map(|segment| match segment {
+                PrcKeyType::StructField(hash) => vec![*hash],
+                PrcKeyType::ListSelect { field, value: SelectorValue::Hash(hash) } => vec![*field, *hash],
+                PrcKeyType::ListSelect { field, .. } => vec![*field],
+                _ => vec![]
+            })
+            .collect();
+        for hash in hashes {
+            if let Some(label) = labels.try_get(hash) {
+                self.add_name(hash, &label);
+            }
+        }
+        self
+    }
+
+    // Unlabeled fields are written as hex, so those need to be read back as the hash itself
+    pub(crate) fn hash_segment(segment: &str, labels: &Labels) -> Hash40 {
+        if segment.starts_with("0x") {
+            if let Ok(hash) = Hash40::from_hex_str(segment) {
+                return hash;
+            }
+        }
+        labels.hash_label(segment)
+    }
+
+    // Reads a string after its opening quote up to its closing quote, and returns what comes after it
+    pub(crate) fn split_quoted(quoted: &str) -> Option<(String, &str)> {
+        let mut string = String::with_capacity(quoted.len());
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => string.push(chars.next()?),
+                '"' => return Some((string, chars.as_str())),
+                c => string.push(c)
+            }
+        }
+        None
+    }
+
+    // Reads the rest of a string after its opening quote, which has to end at its closing quote
+    pub(crate) fn unquote(quoted: &str) -> Option<String> {
+        match Self::split_quoted(quoted)? {
+            (string, "") => Some(string),
+            _ => None
+        }
+    }
+
+    // The position of the `]` that closes a list segment, skipping over any quoted strings
+    pub(crate) fn find_list_end(s: &str) -> Option<usize> {
+        let mut quoted = false;
+        let mut escaped = false;
+        for (idx, c) in s.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                ']' if !quoted => return Some(idx),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    // Parses what is between the brackets of a list segment, `at` is where the segment starts for errors
+    pub(crate) fn parse_list_segment(source: &str, at: &str, inner: &str, labels: &Labels) -> Result<PrcKeyType, Error> {
+        let number = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| Error::parse_at(source, at, format!("list index `{}` is not a number", s)))
+        };
+
+        if inner == "*" {
+            return Ok(PrcKeyType::ListAll);
+        } else if inner == "+" {
+            return Ok(PrcKeyType::ListAppend);
+        } else if let Some(from_end) = inner.strip_prefix('-') {
+            return match number(from_end)? {
+                0 => Err(Error::parse_at(source, at, "list indices from the end start at `[-1]`")),
+                from_end => Ok(PrcKeyType::ListFromEnd(from_end))
+            };
+        }
+
+        // A quoted field name can have `=` in it, so it has to be read before looking for the `=`
+        let needs_both = || Error::parse_at(source, at, format!("selector `{}` needs both a field and a value", inner));
+        let selector = match inner.trim_start().strip_prefix('"') {
+            Some(quoted) => {
+                let (label, rest) = Self::split_quoted(quoted)
+                    .ok_or_else(|| Error::parse_at(source, at, format!("malformed field name in selector `{}`", inner)))?;
+                let value = rest
+                    .trim_start()
+                    .strip_prefix('=')
+                    .ok_or_else(|| Error::parse_at(source, at, format!("expected `=` after the field name in selector `{}`", inner)))?;
+                Some((labels.hash_label(&label), value))
+            },
+            None => match inner.split_once('=') {
+                Some((field, _)) if field.trim().is_empty() => return Err(needs_both()),
+                Some((field, value)) => Some((Self::hash_segment(field.trim(), labels), value)),
+                None => None
+            }
         };
 
-        // Get out if the index str is empty
-        if index == "" {
-            return None;
-        }
-
-        // If we find "." before "[", it means that this first key is a struct and not a list
-        let dot_index = index.find(".").unwrap_or(Self::INVALID_STR_INDEX);
-        let bracket_index = index.find("[").unwrap_or(Self::INVALID_STR_INDEX);
-
-        match dot_index.cmp(&bracket_index) {
-            Ordering::Equal => Some(PrcKey {
-                ty: PrcKeyType::StructField(to_hash40(index)),
-                next: None
-            }),
-            Ordering::Less => {
-                // Split the parent key from the next key and hash it
-                let (parent_key, next_index) = index.split_at(dot_index);
-                let parent_key = to_hash40(parent_key);
-
-                // Return a new PrcKey with the parent key name and the parsed index of the next key
-                Some(PrcKey {
-                    ty: PrcKeyType::StructField(parent_key),
-                    next: Self::get_key(next_index).map(|x| Box::new(x))
-                })
+        if let Some((field, value)) = selector {
+            let value = value.trim();
+            if value.is_empty() {
+                return Err(needs_both());
+            }
+            let value = if let Some(quoted) = value.strip_prefix('"') {
+                let string = Self::unquote(quoted)
+                    .ok_or_else(|| Error::parse_at(source, at, format!("malformed string in selector `{}`", inner)))?;
+                SelectorValue::Str(string)
+            } else if let Ok(int) = value.parse::<i64>() {
+                SelectorValue::Int(int)
+            } else {
+                SelectorValue::Hash(Self::hash_segment(value, labels))
+            };
+            return Ok(PrcKeyType::ListSelect { field, value });
+        }
+
+        match inner.split_once("..") {
+            Some((start, end)) => {
+                let start = if start.is_empty() { 0 } else { number(start)? };
+                let end = if end.is_empty() { None } else { Some(number(end)?) };
+                if matches!(end, Some(end) if end < start) {
+                    return Err(Error::parse_at(source, at, format!("list range `{}` ends before it starts", inner)));
+                }
+                Ok(PrcKeyType::ListRange { start, end })
             },
-            Ordering::Greater => {
-                // Split the parent key from the index
-                let (parent_key, list_idx) = index.split_at(bracket_index);
+            None => number(inner).map(PrcKeyType::ListIndex)
+        }
+    }
+
+    /// Like [`str::parse`], with the labels in the key harvested into `labels` when it is
+    /// [harvesting](Labels::set_harvesting) rather than into the global table
+    pub fn parse_with(source: &str, labels: &Labels) -> Result<Self, Error> {
+        let mut key = PrcKey::new();
+
+        // If the key starts with "." we want to ignore it, i.e. for ".some_struct.some_key"
+        // we only want to worry about "some_struct.some_key"
+        let mut rest = source.strip_prefix('.').unwrap_or(source);
+        while !rest.is_empty() {
+            if let Some(list_idx) = rest.strip_prefix('[') {
                 // Extract the numerical index from inside of the array index
-                let list_end = list_idx.find("]").expect("List index was not terminated!");
-                let (list_key, next_key) = list_idx.split_at(list_end + 1);
-                let list_idx = list_key.trim_start_matches("[").trim_end_matches("]").parse::<usize>().expect("List index was malformed!");
-                // Create the list index prc key
-                let result = PrcKey {
-                    ty: PrcKeyType::ListIndex(list_idx),
-                    next: Self::get_key(next_key).map(|x| Box::new(x))
-                };
-                // Check if the parent key is not empty, if it isn't then add wrap the index key inside of the parent key
-                if parent_key == "" {
-                    Some(result)
-                } else {
-                    let parent_key = to_hash40(parent_key);
-                    Some(PrcKey {
-                        ty: PrcKeyType::StructField(parent_key),
-                        next: Some(Box::new(result))
-                    })
+                let list_end = Self::find_list_end(list_idx)
+                    .ok_or_else(|| Error::parse_at(source, rest, "list index was not terminated"))?;
+                key.push(Self::parse_list_segment(source, rest, &list_idx[..list_end], labels)?);
+
+                rest = &list_idx[list_end + 1..];
+                if !(rest.is_empty() || rest.starts_with('.') || rest.starts_with('[')) {
+                    return Err(Error::parse_at(source, rest, "expected `.` or `[` after a list index"));
+                }
+            } else if let Some(quoted) = rest.strip_prefix('"') {
+                // Quoted field names are always labels, even when they have `.`, `[` or `0x` in them
+                let (label, next) = Self::split_quoted(quoted)
+                    .ok_or_else(|| Error::parse_at(source, rest, "quoted field name was not terminated"))?;
+                if !(next.is_empty() || next.starts_with('.') || next.starts_with('[')) {
+                    return Err(Error::parse_at(source, next, "expected `.` or `[` after a quoted field name"));
+                }
+                let hash = labels.hash_label(&label);
+                key.add_name(hash, &label);
+                key.push(PrcKeyType::StructField(hash));
+                rest = next;
+            } else {
+                // The field name runs until the next struct field or list index
+                let name_end = rest.find(['.', '[']).unwrap_or(rest.len());
+                let (name, next) = rest.split_at(name_end);
+                if name.is_empty() {
+                    return Err(Error::parse_at(source, rest, "expected a field name"));
                 }
+                let hash = Self::hash_segment(name, labels);
+                if !name.starts_with("0x") {
+                    key.add_name(hash, name);
+                }
+                key.push(PrcKeyType::StructField(hash));
+                rest = next;
+            }
+            if let Some(next) = rest.strip_prefix('.') {
+                if next.is_empty() {
+                    return Err(Error::parse_at(source, rest, "expected a field name after `.`"));
+                }
+                rest = next;
             }
         }
+
+        if key.is_empty() {
+            return Err(Error::parse(1, 1, "the key is empty"));
+        }
+        Ok(key)
     }
 
-    fn write_str(&self, hashed: bool) -> String {
-        let current = self.next.as_ref().map(|x| x.write_str(hashed)).unwrap_or("".to_string());
-        match self.ty {
-            PrcKeyType::StructField(name) => {
-                // If we want the field names to stay hashed then we need to just format the hex
-                let name = if hashed {
-                    format!("{:#x}", name.0)
-                } else {
-                    hash::get(name)
-                };
-                format!(".{}{}", name, current)
-            },
-            PrcKeyType::ListIndex(idx) => {
-                // Format an index like an array index operator
-                format!("[{}]{}", idx, current)
+    // like to_string however it allows arguments to stay hashed if that's preferred
+    pub fn to_str(&self, hashed: bool) -> String {
+        self.to_str_with(hashed, Labels::global())
+    }
+
+    /// Like [`to_str`](Self::to_str), with the labels taken from `labels` instead of the global table
+    pub fn to_str_with(&self, hashed: bool, labels: &Labels) -> String {
+        self.write(hashed, &|hash| labels.try_get(hash))
+    }
+
+    // Written with the names the key has for its fields instead of a label table, for errors that name
+    // fields by the table they were resolved with
+    pub(crate) fn to_str_named(&self) -> String {
+        self.write(false, &|hash| self.typed_name(hash).map(str::to_string))
+    }
+
+    fn write(&self, hashed: bool, label: &dyn Fn(Hash40) -> Option<String>) -> String {
+        let mut out = String::new();
+        for segment in self.iter() {
+            match segment {
+                PrcKeyType::StructField(name) => {
+                    if !out.is_empty() {
+                        out.push('.');
+                    }
+                    out.push_str(&write_label(*name, hashed, label));
+                },
+                PrcKeyType::ListIndex(idx) => {
+                    // Format an index like an array index operator
+                    out.push_str(&format!("[{}]", idx));
+                },
+                PrcKeyType::ListAll => out.push_str("[*]"),
+                PrcKeyType::ListRange { start, end: Some(end) } => out.push_str(&format!("[{}..{}]", start, end)),
+                PrcKeyType::ListRange { start, end: None } => out.push_str(&format!("[{}..]", start)),
+                PrcKeyType::ListFromEnd(from_end) => out.push_str(&format!("[-{}]", from_end)),
+                PrcKeyType::ListAppend => out.push_str("[+]"),
+                PrcKeyType::ListSelect { field, value } => {
+                    out.push_str(&format!("[{}={}]", write_label(*field, hashed, label), value.to_str(hashed, label)));
+                }
             }
         }
+        out
     }
 
-    // like to_string however it allows arguments to stay hashed if that's preferred
-    fn to_str(&self, hashed: bool) -> String {
-        self.write_str(hashed).trim_start_matches(".").to_string()
+    /// Like `to_string`, with the labels taken from `labels` instead of the global table
+    pub fn to_string_with(&self, labels: &Labels) -> String {
+        self.to_str_with(false, labels)
     }
 }
 
 impl FromStr for PrcKey {
-    type Err = PrcKeyError;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match Self::get_key(s) {
-            Some(key) => Ok(key),
-            None => Err(PrcKeyError::Malformed)
+        Self::parse_with(s, Labels::global())
+    }
+}
+
+impl fmt::Display for PrcKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_str(false))
+    }
+}
+
+impl From<PrcKeyType> for PrcKey {
+    fn from(segment: PrcKeyType) -> Self {
+        Self { segments: vec![segment], names: vec![] }
+    }
+}
+
+impl FromIterator<PrcKeyType> for PrcKey {
+    fn from_iter<I: IntoIterator<Item = PrcKeyType>>(iter: I) -> Self {
+        Self { segments: iter.into_iter().collect(), names: vec![] }
+    }
+}
+
+impl Extend<PrcKeyType> for PrcKey {
+    fn extend<I: IntoIterator<Item = PrcKeyType>>(&mut self, iter: I) {
+        self.segments.extend(iter);
+    }
+}
+
+impl<'a> IntoIterator for &'a PrcKey {
+    type Item = &'a PrcKeyType;
+    type IntoIter = slice::Iter<'a, PrcKeyType>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for PrcKey {
+    type Item = PrcKeyType;
+    type IntoIter = std::vec::IntoIter<PrcKeyType>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.segments.into_iter()
+    }
+}
+
+// Labels that would be read back as something else if they were written as they are
+pub(crate) fn needs_quotes(label: &str) -> bool {
+    label.is_empty()
+        || label.starts_with("0x")
+        || label.contains(['.', '[', ']', '"', '\\', '='])
+        || label.trim() != label
+}
+
+pub(crate) fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Writes a field name the way the parser reads it: as a label, a quoted label, or hex if there is no label
+fn write_label(hash: Hash40, hashed: bool, label: &dyn Fn(Hash40) -> Option<String>) -> String {
+    match label(hash) {
+        Some(label) if !hashed && needs_quotes(&label) => quote(&label),
+        Some(label) if !hashed => label,
+        _ => format!("{:#x}", hash.0)
+    }
+}
+
+/// Splits a `key = value` line at the first `=` that isn't part of a quoted label or a `[field=value]` selector in the key
+pub(crate) fn split_assignment(line: &str) -> Option<(&str, &str)> {
+    let mut depth = 0usize;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if quoted => {},
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            '=' if depth == 0 => return Some((&line[..idx], &line[idx + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+// Keys used to be a linked list of `{ ty, next }` nodes, and patches written back then still need to load,
+// so binary serializers still get the same nested layout
+struct LinkedSegments<'a>(&'a [PrcKeyType]);
+
+impl Serialize for LinkedSegments<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (ty, next) = self.0
+            .split_first()
+            .ok_or_else(|| S::Error::custom("an empty PrcKey can't be serialized"))?;
+        let mut state = serializer.serialize_struct("PrcKey", 2)?;
+        state.serialize_field("ty", ty)?;
+        state.serialize_field("next", &if next.is_empty() { None } else { Some(LinkedSegments(next)) })?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "PrcKey")]
+struct LinkedKey {
+    ty: PrcKeyType,
+    next: Option<Box<LinkedKey>>
+}
+
+impl LinkedKey {
+    fn into_key(self) -> PrcKey {
+        let mut node = Some(Box::new(self));
+        let mut key = PrcKey::new();
+        while let Some(current) = node {
+            key.push(current.ty);
+            node = current.next;
         }
+        key
     }
 }
 
-impl ToString for PrcKey {
-    fn to_string(&self) -> String {
-        self.write_str(false).trim_start_matches(".").to_string()
+// Human readable formats store keys as their string form, but JSON patches written before that used the linked layout
+struct KeyVisitor;
+
+impl<'de> Visitor<'de> for KeyVisitor {
+    type Value = PrcKey;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a key such as `fighter_param_table[0].walk_accel_mul`")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<PrcKey, E> {
+        if v.is_empty() {
+            return Ok(PrcKey::new());
+        }
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<PrcKey, A::Error> {
+        LinkedKey::deserialize(MapAccessDeserializer::new(map)).map(LinkedKey::into_key)
+    }
+}
+
+/// Human readable serializers like JSON get the key as a string, with hex for unlabeled fields,
+/// while binary ones keep the compact layout that older patches were written with.
+impl Serialize for PrcKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_string())
+        } else {
+            LinkedSegments(&self.segments).serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PrcKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(KeyVisitor)
+        } else {
+            LinkedKey::deserialize(deserializer).map(LinkedKey::into_key)
+        }
     }
 }
 
@@ -142,7 +713,8 @@ fn hash_test() {
 
 #[test]
 fn unhash_test() {
-    hash::add_hashes(vec![
+    let labels = Labels::new();
+    labels.add_all(vec![
         "test_struct",
         "test_field",
         "test_field_2"
@@ -152,12 +724,13 @@ fn unhash_test() {
         Err(e) => panic!("Failed to parse key: {:?}", e)
     };
 
-    assert_eq!("test_struct.test_field.test_field_2", key.to_string());
+    assert_eq!("test_struct.test_field.test_field_2", key.to_string_with(&labels));
 }
 
 #[test]
 fn unhash_list_test() {
-    hash::add_hashes(vec![
+    let labels = Labels::new();
+    labels.add_all(vec![
         "test_list",
         "test_struct",
         "test_field",
@@ -168,7 +741,7 @@ fn unhash_list_test() {
         Err(e) => panic!("Failed to parse key: {:?}", e)
     };
 
-    assert_eq!("test_list[1].test_struct.test_field[3].test_field_2", key.to_string());
+    assert_eq!("test_list[1].test_struct.test_field[3].test_field_2", key.to_string_with(&labels));
 }
 
 #[test]
@@ -181,3 +754,174 @@ fn hash_list_test() {
     let test = format!("{:#x}[1].{:#x}.{:#x}[3].{:#x}", to_hash40("test_list").0, to_hash40("test_struct").0, to_hash40("test_field").0, to_hash40("test_field_2").0);
     assert_eq!(test, key.to_str(true));
 }
+
+#[test]
+fn hex_roundtrip_test() {
+    let key: PrcKey = match "0x0123456789[2].0x1fedcba987".parse() {
+        Ok(key) => key,
+        Err(e) => panic!("Failed to parse key: {:?}", e)
+    };
+
+    assert_eq!("0x123456789[2].0x1fedcba987", key.to_str(true));
+}
+
+#[test]
+fn malformed_key_test() {
+    for (key, column) in [("test_list[1", 10), ("test_list[x].test_field", 10), ("test_struct..test_field", 13), ("test_list[1]test_field", 13), ("test_struct.", 12), ("test_list[1].", 13)].iter() {
+        match key.parse::<PrcKey>() {
+            Err(Error::Parse { line: 1, column: c, .. }) if c == *column => {},
+            Err(e) => panic!("Unexpected error for `{}`: {}", key, e),
+            Ok(_) => panic!("Malformed key `{}` was accepted", key)
+        }
+    }
+    assert!("".parse::<PrcKey>().is_err());
+}
+
+#[test]
+fn path_api_test() {
+    let key: PrcKey = "test_list[1].test_struct.test_field".parse().unwrap();
+    let parent: PrcKey = "test_list[1].test_struct".parse().unwrap();
+    let list: PrcKey = "test_list".parse().unwrap();
+
+    assert_eq!(key.parent(), Some(parent.clone()));
+    assert!(key.starts_with(&list));
+    assert!(!list.starts_with(&key));
+    assert_eq!(key.strip_prefix(&list).map(|x| x.to_str(true)), Some(format!("[1].{:#x}.{:#x}", to_hash40("test_struct").0, to_hash40("test_field").0)));
+    assert_eq!(list.join(&key.strip_prefix(&list).unwrap()), key);
+
+    let mut pushed = parent.clone();
+    pushed.push(PrcKeyType::StructField(to_hash40("test_field")));
+    assert_eq!(pushed, key);
+    assert_eq!(pushed.pop(), Some(PrcKeyType::StructField(to_hash40("test_field"))));
+    assert_eq!(pushed, parent);
+    assert_eq!(key.iter().count(), 4);
+    assert_eq!(PrcKey::new().parent(), None);
+
+    // Parents sort before their children and list indices sort numerically
+    let mut keys: Vec<PrcKey> = ["test_list[10]", "test_list[2].test_field", "test_list", "test_list[2]"]
+        .iter()
+        .map(|x| x.parse().unwrap())
+        .collect();
+    keys.sort();
+    assert_eq!(keys.iter().map(|x| x.to_str(true).split_off(11)).collect::<Vec<_>>(), vec!["", "[2]", &format!("[2].{:#x}", to_hash40("test_field").0), "[10]"]);
+}
+
+#[test]
+fn legacy_serialized_form_test() {
+    // The layout that keys had when they were a linked list
+    #[derive(Serialize)]
+    struct OldKey {
+        ty: PrcKeyType,
+        next: Option<Box<OldKey>>
+    }
+
+    let old = OldKey {
+        ty: PrcKeyType::StructField(to_hash40("test_list")),
+        next: Some(Box::new(OldKey {
+            ty: PrcKeyType::ListIndex(3),
+            next: None
+        }))
+    };
+    let data = bincode::serialize(&old).unwrap();
+    let key: PrcKey = bincode::deserialize(&data).unwrap();
+    assert_eq!(key, "test_list[3]".parse().unwrap());
+    assert_eq!(bincode::serialize(&key).unwrap(), data);
+}
+
+#[test]
+fn list_pattern_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["test_list", "test_field"]);
+
+    for (key, concrete) in [("test_list[*].test_field", false), ("test_list[3..10]", false), ("test_list[3..]", false), ("test_list[0..2][1]", false), ("test_list[1]", true)].iter() {
+        let parsed: PrcKey = key.parse().unwrap();
+        assert_eq!(parsed.is_concrete(), *concrete);
+        assert_eq!(parsed.to_string_with(&labels), *key);
+    }
+
+    let key: PrcKey = "test_list[..4]".parse().unwrap();
+    assert_eq!(key.last(), Some(&PrcKeyType::ListRange { start: 0, end: Some(4) }));
+    assert!("test_list[4..2]".parse::<PrcKey>().is_err());
+    assert!("test_list[a..2]".parse::<PrcKey>().is_err());
+}
+
+#[test]
+fn list_select_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["test_list", "test_field", "test_kind", "test_kind_a"]);
+
+    for key in ["test_list[test_kind=test_kind_a].test_field", "test_list[test_field=-3]", "test_list[test_field=\"a]=\\\"b\"][0]"].iter() {
+        let parsed: PrcKey = key.parse().unwrap();
+        assert!(!parsed.is_concrete());
+        assert_eq!(parsed.to_string_with(&labels), *key);
+    }
+
+    let key: PrcKey = "test_list[test_field=\"a]=\\\"b\"]".parse().unwrap();
+    assert_eq!(key.last(), Some(&PrcKeyType::ListSelect { field: to_hash40("test_field"), value: SelectorValue::Str("a]=\"b".to_string()) }));
+    assert!(SelectorValue::Int(3).matches(&ParamKind::U8(3)));
+    assert!(!SelectorValue::Int(3).matches(&ParamKind::Float(3.0)));
+    assert!("test_list[=1]".parse::<PrcKey>().is_err());
+    assert!("test_list[test_field=\"a]".parse::<PrcKey>().is_err());
+}
+
+#[test]
+fn quoted_label_test() {
+    let quoted = ["test.dotted", "test[0]", "0xtest", "test\"quoted\\", "test=eq", " test", ""];
+    let labels = Labels::new();
+    labels.add_all(quoted.iter());
+
+    for label in quoted.iter() {
+        let key = PrcKey::from_iter(vec![
+            PrcKeyType::StructField(to_hash40(label)),
+            PrcKeyType::ListSelect { field: to_hash40(label), value: SelectorValue::Hash(to_hash40(label)) },
+            PrcKeyType::StructField(to_hash40(label)),
+        ]);
+        let written = key.to_string_with(&labels);
+        assert_eq!(PrcKey::parse_with(&written, &labels).unwrap(), key, "`{}` did not round trip", written);
+        assert_eq!(split_assignment(&format!("{} = 1u8", written)).map(|(key, _)| key.trim()), Some(written.as_str()));
+    }
+
+    assert_eq!("\"test.dotted\".test_field".parse::<PrcKey>().unwrap().first(), Some(&PrcKeyType::StructField(to_hash40("test.dotted"))));
+    assert_eq!("\"0x10\"".parse::<PrcKey>().unwrap().first(), Some(&PrcKeyType::StructField(to_hash40("0x10"))));
+    assert!("\"test.dotted".parse::<PrcKey>().is_err());
+    assert!("\"test\"field".parse::<PrcKey>().is_err());
+}
+
+#[test]
+fn end_relative_index_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["test_list", "test_field"]);
+
+    for (key, concrete) in [("test_list[-1].test_field", true), ("test_list[-12]", true), ("test_list[+]", false)].iter() {
+        let parsed: PrcKey = key.parse().unwrap();
+        assert_eq!(parsed.is_concrete(), *concrete);
+        assert_eq!(parsed.to_string_with(&labels), *key);
+    }
+
+    assert_eq!("test_list[-2]".parse::<PrcKey>().unwrap().last(), Some(&PrcKeyType::ListFromEnd(2)));
+    assert!("test_list[-0]".parse::<PrcKey>().is_err());
+    assert!("test_list[-]".parse::<PrcKey>().is_err());
+}
+
+#[test]
+fn builder_test() {
+    let key = PrcKey::field("test_list").index(1).field("test.dotted").from_end(1).all().append().hash(Hash40(0x10)).build();
+    assert_eq!(key, "test_list[1].\"test.dotted\"[-1][*][+].0x10".parse().unwrap());
+    assert_eq!(PrcKey::from(PrcKey::builder().index(2)), "[2]".parse().unwrap());
+}
+
+#[test]
+fn human_readable_serde_test() {
+    // Keys are written with the global labels, so this one has none to stay independent of them
+    let key: PrcKey = "0x10[3][*].0x20".parse().unwrap();
+
+    let json = serde_json::to_string(&key).unwrap();
+    assert_eq!(json, "\"0x10[3][*].0x20\"");
+    assert_eq!(serde_json::from_str::<PrcKey>(&json).unwrap(), key);
+    assert_eq!(serde_json::from_str::<PrcKey>("\"\"").unwrap(), PrcKey::new());
+    assert!(serde_json::from_str::<PrcKey>("\"test_list[\"").is_err());
+
+    // JSON written with the linked layout still loads
+    let linked = serde_json::json!({ "ty": { "StructField": "test_list" }, "next": { "ty": { "ListIndex": 3 }, "next": null } });
+    assert_eq!(serde_json::from_value::<PrcKey>(linked).unwrap(), "test_list[3]".parse().unwrap());
+}
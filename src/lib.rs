@@ -1,5 +1,15 @@
+pub mod access;
+pub mod crack;
 pub mod diff;
+pub mod dump;
+pub mod error;
+pub mod format;
 pub mod hash;
 pub mod key;
+#[doc(hidden)]
+pub mod literal;
+pub mod query;
+pub mod tree;
 
+pub use error::{Error, Result};
 pub use prc;
\ No newline at end of file
@@ -0,0 +1,473 @@
+//! The compile time half of [`prc_key!`](crate::prc_key). Everything in here runs in `const` items that
+//! the macro expands to, so a malformed key fails to compile and every label is hashed by the compiler.
+//! It follows the same grammar as [`PrcKey::from_str`](std::str::FromStr), rule for rule, so the two
+//! have to be changed together.
+
+use prc::hash40::Hash40;
+
+use crate::key::{
+    PrcKey,
+    PrcKeyType,
+    SelectorValue
+};
+
+/// Builds a [`PrcKey`] from a string literal. The key is checked and every label in it is hashed at
+/// compile time, so a malformed key is a compile error rather than a panic.
+///
+/// ```
+/// let key = prcx::prc_key!("fighter_param_table[0].walk_accel_mul");
+/// assert_eq!(key, "fighter_param_table[0].walk_accel_mul".parse().unwrap());
+/// ```
+///
+/// Anything that [`PrcKey::from_str`](std::str::FromStr) rejects doesn't compile:
+///
+/// ```compile_fail
+/// let key = prcx::prc_key!("fighter_param_table[0");
+/// ```
+/// ```compile_fail
+/// let key = prcx::prc_key!("a[-0]");
+/// ```
+/// ```compile_fail
+/// let key = prcx::prc_key!("a[-]");
+/// ```
+/// ```compile_fail
+/// let key = prcx::prc_key!("a[4..2]");
+/// ```
+/// ```compile_fail
+/// let key = prcx::prc_key!("a[1..2..3]");
+/// ```
+/// ```compile_fail
+/// let key = prcx::prc_key!("a[*5]");
+/// ```
+/// ```compile_fail
+/// let key = prcx::prc_key!("a[--1]");
+/// ```
+#[macro_export]
+macro_rules! prc_key {
+    ($key:literal) => {{
+        const LEN: usize = $crate::literal::count($key);
+        const SEGMENTS: [$crate::literal::LiteralSegment; LEN] = $crate::literal::parse::<LEN>($key);
+        $crate::literal::to_key($key, &SEGMENTS)
+    }};
+}
+
+/// Where a label is in the literal. Quoted spans start after the opening quote and end after the
+/// closing one, which is how [`PrcKey::unquote`] takes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    start: usize,
+    end: usize,
+    quoted: bool
+}
+
+/// A [`PrcKeyType`] that can be built in a `const` context
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiteralSegment {
+    /// A struct field, with where its name is when it was written as a label
+    Field(u64, Option<Span>),
+    Index(usize),
+    All,
+    Range(usize, Option<usize>),
+    FromEnd(usize),
+    Append,
+    SelectHash(u64, u64),
+    SelectInt(u64, i64),
+    SelectStr(u64, Span)
+}
+
+impl Span {
+    fn text(self, literal: &str) -> String {
+        let text = &literal[self.start..self.end];
+        match self.quoted {
+            // Checked at compile time, so the string is always terminated
+            true => PrcKey::unquote(text).unwrap_or_default(),
+            false => text.to_string()
+        }
+    }
+}
+
+impl LiteralSegment {
+    fn to_segment(self, literal: &str) -> PrcKeyType {
+        match self {
+            LiteralSegment::Field(hash, _) => PrcKeyType::StructField(Hash40(hash)),
+            LiteralSegment::Index(idx) => PrcKeyType::ListIndex(idx),
+            LiteralSegment::All => PrcKeyType::ListAll,
+            LiteralSegment::Range(start, end) => PrcKeyType::ListRange { start, end },
+            LiteralSegment::FromEnd(from_end) => PrcKeyType::ListFromEnd(from_end),
+            LiteralSegment::Append => PrcKeyType::ListAppend,
+            LiteralSegment::SelectHash(field, value) => PrcKeyType::ListSelect {
+                field: Hash40(field),
+                value: SelectorValue::Hash(Hash40(value))
+            },
+            LiteralSegment::SelectInt(field, value) => PrcKeyType::ListSelect {
+                field: Hash40(field),
+                value: SelectorValue::Int(value)
+            },
+            LiteralSegment::SelectStr(field, span) => PrcKeyType::ListSelect {
+                field: Hash40(field),
+                value: SelectorValue::Str(span.text(literal))
+            }
+        }
+    }
+}
+
+/// Builds the key out of the segments that [`parse`] worked out of `literal`
+pub fn to_key(literal: &str, segments: &[LiteralSegment]) -> PrcKey {
+    let mut key: PrcKey = segments.iter().map(|segment| segment.to_segment(literal)).collect();
+    for segment in segments.iter() {
+        if let LiteralSegment::Field(hash, Some(span)) = segment {
+            key.add_name(Hash40(*hash), &span.text(literal));
+        }
+    }
+    key
+}
+
+const fn crc32(bytes: &[u8], start: usize, end: usize, escaped: bool) -> (u32, u64) {
+    let mut crc = 0xFFFF_FFFFu32;
+    let mut len = 0u64;
+    let mut idx = start;
+    while idx < end {
+        // Quoted labels are hashed without their escapes
+        if escaped && bytes[idx] == b'\\' {
+            idx += 1;
+        }
+        crc ^= bytes[idx] as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        len += 1;
+        idx += 1;
+    }
+    (!crc, len)
+}
+
+const fn hash40(bytes: &[u8], start: usize, end: usize, escaped: bool) -> u64 {
+    let (crc, len) = crc32(bytes, start, end, escaped);
+    crc as u64 | (len << 32)
+}
+
+// The char that starts at `idx` and how many bytes it takes, literals always being valid UTF-8
+const fn char_at(bytes: &[u8], idx: usize) -> (u32, usize) {
+    let first = bytes[idx] as u32;
+    let (mut c, len) = if first < 0x80 {
+        return (first, 1);
+    } else if first < 0xE0 {
+        (first & 0x1F, 2)
+    } else if first < 0xF0 {
+        (first & 0x0F, 3)
+    } else {
+        (first & 0x07, 4)
+    };
+    let mut next = 1;
+    while next < len {
+        c = (c << 6) | (bytes[idx + next] as u32 & 0x3F);
+        next += 1;
+    }
+    (c, len)
+}
+
+// Like `char::is_whitespace`, which is what `str::trim` goes by
+const fn is_space(c: u32) -> bool {
+    matches!(c, 0x09..=0x0D | 0x20 | 0x85 | 0xA0 | 0x1680 | 0x2000..=0x200A | 0x2028 | 0x2029 | 0x202F | 0x205F | 0x3000)
+}
+
+const fn trim(bytes: &[u8], mut start: usize, mut end: usize) -> (usize, usize) {
+    while start < end {
+        let (c, len) = char_at(bytes, start);
+        if !is_space(c) {
+            break;
+        }
+        start += len;
+    }
+    while end > start {
+        let mut last = end - 1;
+        while bytes[last] & 0xC0 == 0x80 {
+            last -= 1;
+        }
+        if !is_space(char_at(bytes, last).0) {
+            break;
+        }
+        end = last;
+    }
+    (start, end)
+}
+
+const fn find(bytes: &[u8], start: usize, end: usize, needle: &[u8]) -> Option<usize> {
+    let mut idx = start;
+    while idx + needle.len() <= end {
+        let mut matched = 0;
+        while matched < needle.len() && bytes[idx + matched] == needle[matched] {
+            matched += 1;
+        }
+        if matched == needle.len() {
+            return Some(idx);
+        }
+        idx += 1;
+    }
+    None
+}
+
+// Like `u64::from_str_radix`, including the optional `+`
+const fn number(bytes: &[u8], start: usize, end: usize, radix: u64) -> Option<u64> {
+    let mut idx = if start < end && bytes[start] == b'+' { start + 1 } else { start };
+    if idx == end {
+        return None;
+    }
+    let mut value = 0u64;
+    while idx < end {
+        let digit = match bytes[idx] {
+            c @ b'0'..=b'9' => (c - b'0') as u64,
+            c @ b'a'..=b'f' => (c - b'a') as u64 + 10,
+            c @ b'A'..=b'F' => (c - b'A') as u64 + 10,
+            _ => return None
+        };
+        if digit >= radix {
+            return None;
+        }
+        value = match value.checked_mul(radix) {
+            Some(value) => match value.checked_add(digit) {
+                Some(value) => value,
+                None => return None
+            },
+            None => return None
+        };
+        idx += 1;
+    }
+    Some(value)
+}
+
+// Like `str::parse::<usize>`
+const fn index(bytes: &[u8], start: usize, end: usize) -> usize {
+    match number(bytes, start, end, 10) {
+        Some(idx) if idx <= usize::MAX as u64 => idx as usize,
+        _ => panic!("list index is not a number")
+    }
+}
+
+// Like `str::parse::<i64>`
+const fn int(bytes: &[u8], start: usize, end: usize) -> Option<i64> {
+    if start < end && bytes[start] == b'-' {
+        match number(bytes, start + 1, end, 10) {
+            // `-+1` isn't a number
+            _ if start + 1 < end && bytes[start + 1] == b'+' => None,
+            Some(value) if value <= i64::MAX as u64 + 1 => Some((value as i64).wrapping_neg()),
+            _ => None
+        }
+    } else {
+        match number(bytes, start, end, 10) {
+            Some(value) if value <= i64::MAX as u64 => Some(value as i64),
+            _ => None
+        }
+    }
+}
+
+// Like `PrcKey::hash_segment`: labels starting with `0x` are hashes, unless they don't parse as one
+const fn hash_segment(bytes: &[u8], start: usize, end: usize) -> u64 {
+    if end - start >= 2 && bytes[start] == b'0' && bytes[start + 1] == b'x' {
+        if let Some(hash) = number(bytes, start + 2, end, 16) {
+            return hash;
+        }
+    }
+    hash40(bytes, start, end, false)
+}
+
+// The position of the closing quote of a string that starts at `start`, or `end` if it isn't closed
+const fn quote_end(bytes: &[u8], start: usize, end: usize) -> usize {
+    let mut idx = start;
+    while idx < end {
+        match bytes[idx] {
+            b'\\' => idx += 1,
+            b'"' => return idx,
+            _ => {}
+        }
+        idx += 1;
+    }
+    end
+}
+
+// Like `PrcKey::find_list_end`, the position of the `]` that closes a list segment
+const fn list_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut idx = start;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'"' => idx = quote_end(bytes, idx + 1, bytes.len()),
+            b']' => return Some(idx),
+            _ => {}
+        }
+        idx += 1;
+    }
+    None
+}
+
+// The value of a `[field=value]` selector, between `start` and `end`
+const fn selector(bytes: &[u8], start: usize, end: usize, field: u64) -> LiteralSegment {
+    let (start, end) = trim(bytes, start, end);
+    if start == end {
+        panic!("selector needs both a field and a value");
+    }
+    if bytes[start] == b'"' {
+        if quote_end(bytes, start + 1, end) != end - 1 {
+            panic!("malformed string in selector");
+        }
+        return LiteralSegment::SelectStr(field, Span { start: start + 1, end, quoted: true });
+    }
+    match int(bytes, start, end) {
+        Some(value) => LiteralSegment::SelectInt(field, value),
+        None => LiteralSegment::SelectHash(field, hash_segment(bytes, start, end))
+    }
+}
+
+// Like `PrcKey::parse_list_segment`, for what is between `start` and `end`, the brackets of a list segment
+const fn list_segment(bytes: &[u8], start: usize, end: usize) -> LiteralSegment {
+    if end - start == 1 && bytes[start] == b'*' {
+        return LiteralSegment::All;
+    } else if end - start == 1 && bytes[start] == b'+' {
+        return LiteralSegment::Append;
+    } else if start < end && bytes[start] == b'-' {
+        return match index(bytes, start + 1, end) {
+            0 => panic!("list indices from the end start at `[-1]`"),
+            from_end => LiteralSegment::FromEnd(from_end)
+        };
+    }
+
+    // A quoted field name can have `=` in it, so it has to be read before looking for the `=`
+    let (field_start, _) = trim(bytes, start, end);
+    if field_start < end && bytes[field_start] == b'"' {
+        let close = quote_end(bytes, field_start + 1, end);
+        if close == end {
+            panic!("malformed field name in selector");
+        }
+        let (eq, _) = trim(bytes, close + 1, end);
+        if eq == end || bytes[eq] != b'=' {
+            panic!("expected `=` after the field name in selector");
+        }
+        return selector(bytes, eq + 1, end, hash40(bytes, field_start + 1, close, true));
+    }
+    if let Some(eq) = find(bytes, start, end, b"=") {
+        let (field_start, field_end) = trim(bytes, start, eq);
+        if field_start == field_end {
+            panic!("selector needs both a field and a value");
+        }
+        return selector(bytes, eq + 1, end, hash_segment(bytes, field_start, field_end));
+    }
+
+    match find(bytes, start, end, b"..") {
+        Some(dots) => {
+            let range_start = if dots == start { 0 } else { index(bytes, start, dots) };
+            let range_end = if dots + 2 == end { None } else { Some(index(bytes, dots + 2, end)) };
+            if let Some(range_end) = range_end {
+                if range_end < range_start {
+                    panic!("list range ends before it starts");
+                }
+            }
+            LiteralSegment::Range(range_start, range_end)
+        },
+        None => LiteralSegment::Index(index(bytes, start, end))
+    }
+}
+
+// Like one turn of the loop in `PrcKey::parse_with`, reads the segment at `idx` and returns it along
+// with where the next one starts
+const fn next_segment(bytes: &[u8], idx: usize) -> (LiteralSegment, usize) {
+    let (segment, next) = if bytes[idx] == b'[' {
+        let end = match list_end(bytes, idx + 1) {
+            Some(end) => end,
+            None => panic!("list index was not terminated")
+        };
+        let next = end + 1;
+        if next < bytes.len() && bytes[next] != b'.' && bytes[next] != b'[' {
+            panic!("expected `.` or `[` after a list index");
+        }
+        (list_segment(bytes, idx + 1, end), next)
+    } else if bytes[idx] == b'"' {
+        // Quoted field names are always labels, even when they have `.`, `[` or `0x` in them
+        let close = quote_end(bytes, idx + 1, bytes.len());
+        if close == bytes.len() {
+            panic!("quoted field name was not terminated");
+        }
+        let next = close + 1;
+        if next < bytes.len() && bytes[next] != b'.' && bytes[next] != b'[' {
+            panic!("expected `.` or `[` after a quoted field name");
+        }
+        let name = Span { start: idx + 1, end: next, quoted: true };
+        (LiteralSegment::Field(hash40(bytes, idx + 1, close, true), Some(name)), next)
+    } else {
+        let mut end = idx;
+        while end < bytes.len() && bytes[end] != b'.' && bytes[end] != b'[' {
+            end += 1;
+        }
+        if end == idx {
+            panic!("expected a field name");
+        }
+        let is_hex = end - idx >= 2 && bytes[idx] == b'0' && bytes[idx + 1] == b'x';
+        let name = if is_hex { None } else { Some(Span { start: idx, end, quoted: false }) };
+        (LiteralSegment::Field(hash_segment(bytes, idx, end), name), end)
+    };
+
+    if next < bytes.len() && bytes[next] == b'.' {
+        if next + 1 == bytes.len() {
+            panic!("expected a field name after `.`");
+        }
+        return (segment, next + 1);
+    }
+    (segment, next)
+}
+
+const fn first_segment(bytes: &[u8]) -> usize {
+    if !bytes.is_empty() && bytes[0] == b'.' { 1 } else { 0 }
+}
+
+/// Checks the whole key and counts its segments, which is how long the array from [`parse`] has to be
+pub const fn count(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut idx = first_segment(bytes);
+    let mut len = 0;
+    while idx < bytes.len() {
+        idx = next_segment(bytes, idx).1;
+        len += 1;
+    }
+    if len == 0 {
+        panic!("the key is empty");
+    }
+    len
+}
+
+/// Reads every segment of the key, `N` being what [`count`] returned for it
+pub const fn parse<const N: usize>(s: &str) -> [LiteralSegment; N] {
+    let bytes = s.as_bytes();
+    let mut segments = [LiteralSegment::All; N];
+    let mut idx = first_segment(bytes);
+    let mut len = 0;
+    while idx < bytes.len() {
+        let (segment, next) = next_segment(bytes, idx);
+        segments[len] = segment;
+        idx = next;
+        len += 1;
+    }
+    segments
+}
+
+#[test]
+fn literal_matches_parser_test() {
+    use prc::hash40::to_hash40;
+
+    for (literal, key) in [
+        (prc_key!("literal_table[0].literal_field"), "literal_table[0].literal_field"),
+        (prc_key!(".literal_table[*][2..][..4][3..5][+7][-+2]"), ".literal_table[*][2..][..4][3..5][+7][-+2]"),
+        (prc_key!("literal_table[-1][+].0x10.0xzz.0x+1f"), "literal_table[-1][+].0x10.0xzz.0x+1f"),
+        (prc_key!("literal_table[ literal_field = literal_kind ][literal_field=-3][0x10=+7][a=-+7][b=-9223372036854775808]"), "literal_table[ literal_field = literal_kind ][literal_field=-3][0x10=+7][a=-+7][b=-9223372036854775808]"),
+        (prc_key!("literal_table[\"literal kind\"=\"a]\\\"b\"].\"literal.field\\\\\""), "literal_table[\"literal kind\"=\"a]\\\"b\"].\"literal.field\\\\\""),
+        (prc_key!("literal_table[\u{3000}literal_field\u{a0}= \u{2003}literal_kind\u{85}].lit\u{e9}ral"), "literal_table[\u{3000}literal_field\u{a0}= \u{2003}literal_kind\u{85}].lit\u{e9}ral"),
+    ].iter() {
+        assert_eq!(*literal, key.parse::<PrcKey>().unwrap(), "`{}` was read differently", key);
+    }
+    assert_eq!(hash40(b"literal_field", 0, 13, false), to_hash40("literal_field").0);
+
+    // Field names are kept as they were typed, like the parser does
+    let key = prc_key!("literal_table.\"literal quoted\\\"\".0x10");
+    assert_eq!(key.typed_name(to_hash40("literal_table")), Some("literal_table"));
+    assert_eq!(key.typed_name(to_hash40("literal quoted\"")), Some("literal quoted\""));
+    assert_eq!(key.typed_name(Hash40(0x10)), None);
+}
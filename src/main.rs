@@ -0,0 +1,514 @@
+use std::{
+    env,
+    fmt,
+    path::{Path, PathBuf},
+    process
+};
+
+use prcx::{
+    access::ParamAccess,
+    diff::Diff,
+    dump,
+    format::Format,
+    hash::{self, LabelFormat, Labels},
+    key::{PrcKey, PrcKeyType},
+    prc::{ParamKind, ParamStruct},
+    tree
+};
+
+const USAGE: &str = "\
+usage: prcx [options] <command> ...
+
+commands:
+    diff <vanilla.prc> <modded.prc> -o <patch>    write the changes from vanilla to modded as a patch
+    diff <vanilla-dir> <modded-dir> -o <out-dir>   write a patch for every .prc that differs between the
+                                                   directories, as .prcx or .prctxt for --format text
+    apply <patch> <in.prc> -o <out.prc>            apply a patch to a param file
+    convert <patch> -o <patch>                     rewrite a patch in another format
+    batch <vanilla-dir> <mod-dir>... -o <out-dir>  apply every .prcx and .prctxt patch in the mod directories
+                                                   to the .prc at the same path under the vanilla directory
+    show <file.prc> [key]                          print a param file, or the params at a key, as a tree
+    get <file.prc> <key>                           print the value at a key, or every value a pattern covers
+    set <file.prc> <key> <value> [-o <out.prc>]    change the values at a key, in place unless -o is given
+
+    Keys and values are written the same way as in text patches, like
+    `fighter_param_table[fighter_kind=fighter_kind_mario].walk_speed_max` and `1.5`.
+
+options:
+    -o, --output <path>    where to write the result
+    --                     treat everything after this as a command or argument, not an option
+    --labels <file>        read labels from a file, `.csv` files as `0xhash,label` lines and
+                           anything else as one label per line. Can be given more than once.
+    --format <format>      text, binary, legacy, json or xml. By default this comes from the
+                           extension of the output: .txt or .prctxt, .json and .xml, and binary otherwise.
+    --embed-labels         store the labels a patch uses in it, for binary and JSON patches
+    --strict               apply nothing unless every entry of the patch fits, and for a diff of
+                           two files, write no patch unless it holds every change
+    -h, --help             print this message
+    -V, --version          print the version
+
+exit codes:
+    0    success
+    1    something failed, such as a file that couldn't be read or a malformed patch
+    2    the command line was wrong
+    3    the patch was applied, but some of its entries didn't fit and were skipped, or
+         diff wrote its patches but left out changes that a patch can't hold.
+         batch and diff exit with 1 instead if a patch or param file couldn't be read.
+";
+
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_INCOMPLETE: i32 = 3;
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Diff {
+        vanilla: PathBuf,
+        modded: PathBuf
+    },
+    Apply {
+        patch: PathBuf,
+        input: PathBuf
+    },
+    Convert {
+        patch: PathBuf
+    },
+    Batch {
+        vanilla: PathBuf,
+        mods: Vec<PathBuf>
+    },
+    Show {
+        file: PathBuf,
+        key: Option<String>
+    },
+    Get {
+        file: PathBuf,
+        key: String
+    },
+    Set {
+        file: PathBuf,
+        key: String,
+        value: String
+    },
+    Help,
+    Version
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    command: Command,
+    output: Option<PathBuf>,
+    labels: Vec<PathBuf>,
+    format: Option<Format>,
+    embed_labels: bool,
+    strict: bool
+}
+
+#[derive(Debug)]
+enum CliError {
+    /// The command line was wrong
+    Usage(String),
+    /// The command ran and failed
+    Failed(String),
+    /// The patch was applied with entries left out
+    Incomplete(String)
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => EXIT_USAGE,
+            CliError::Failed(_) => EXIT_FAILURE,
+            CliError::Incomplete(_) => EXIT_INCOMPLETE
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(message) => write!(f, "{}\nrun `prcx --help` for usage", message),
+            CliError::Failed(message) | CliError::Incomplete(message) => f.write_str(message)
+        }
+    }
+}
+
+// Attaches the file that an error happened with
+fn failed<E: fmt::Display>(path: &Path) -> impl FnOnce(E) -> CliError + '_ {
+    move |error| CliError::Failed(format!("{}: {}", path.display(), error))
+}
+
+fn parse_format(name: &str) -> Option<Format> {
+    let format = match name {
+        "text" | "txt" => Format::Text,
+        "binary" | "bin" => Format::Binary,
+        "legacy" => Format::LegacyBinary,
+        "json" => Format::Json,
+        "xml" => Format::Xml,
+        _ => return None
+    };
+    Some(format)
+}
+
+fn format_for(path: &Path) -> Format {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("txt") | Some("prctxt") => Format::Text,
+        Some("json") => Format::Json,
+        Some("xml") => Format::Xml,
+        _ => Format::Binary
+    }
+}
+
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Args, CliError> {
+    let mut args = args.into_iter();
+    let mut positional = vec![];
+    let mut output = None;
+    let mut labels = vec![];
+    let mut format = None;
+    let mut embed_labels = false;
+    let mut strict = false;
+    let mut help = false;
+    let mut version = false;
+
+    while let Some(arg) = args.next() {
+        // Negative numbers are values, not options
+        if arg == "--" {
+            positional.extend(args.by_ref());
+            break;
+        } else if arg.parse::<f64>().is_ok() {
+            positional.push(arg);
+            continue;
+        }
+
+        let mut value = |name: &str| args.next().ok_or_else(|| CliError::Usage(format!("`{}` needs a value", name)));
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(PathBuf::from(value(&arg)?)),
+            "--labels" => labels.push(PathBuf::from(value(&arg)?)),
+            "--format" => {
+                let name = value(&arg)?;
+                format = Some(parse_format(&name).ok_or_else(|| CliError::Usage(format!("unknown format `{}`", name)))?);
+            },
+            "--embed-labels" => embed_labels = true,
+            "--strict" => strict = true,
+            "-h" | "--help" => help = true,
+            "-V" | "--version" => version = true,
+            flag if flag.starts_with('-') && flag != "-" => return Err(CliError::Usage(format!("unknown option `{}`", flag))),
+            _ => positional.push(arg)
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command = match positional.next() {
+        _ if help => Command::Help,
+        _ if version => Command::Version,
+        None => return Err(CliError::Usage("no command was given".to_string())),
+        Some(name) => {
+            let mut operand = |what: &str| {
+                positional
+                    .next()
+                    .ok_or_else(|| CliError::Usage(format!("`{}` needs {}", name, what)))
+            };
+            let command = match name.as_str() {
+                "diff" => Command::Diff { vanilla: operand("a vanilla param file")?.into(), modded: operand("a modded param file")?.into() },
+                "apply" => Command::Apply { patch: operand("a patch")?.into(), input: operand("a param file")?.into() },
+                "convert" => Command::Convert { patch: operand("a patch")?.into() },
+                "batch" => {
+                    let vanilla = operand("a vanilla directory")?.into();
+                    let mods: Vec<PathBuf> = positional.by_ref().map(PathBuf::from).collect();
+                    if mods.is_empty() {
+                        return Err(CliError::Usage("`batch` needs at least one mod directory".to_string()));
+                    }
+                    Command::Batch { vanilla, mods }
+                },
+                "show" => Command::Show { file: operand("a param file")?.into(), key: positional.next() },
+                "get" => Command::Get { file: operand("a param file")?.into(), key: operand("a key")? },
+                "set" => Command::Set { file: operand("a param file")?.into(), key: operand("a key")?, value: operand("a value")? },
+                _ => return Err(CliError::Usage(format!("unknown command `{}`", name)))
+            };
+            if let Some(extra) = positional.next() {
+                return Err(CliError::Usage(format!("unexpected argument `{}`", extra)));
+            }
+            let needs_output = matches!(command, Command::Diff { .. } | Command::Apply { .. } | Command::Convert { .. } | Command::Batch { .. });
+            if needs_output && output.is_none() {
+                return Err(CliError::Usage(format!("`{}` needs an output, given with `-o`", name)));
+            }
+            command
+        }
+    };
+
+    Ok(Args { command, output, labels, format, embed_labels, strict })
+}
+
+fn load_labels(path: &Path) -> Result<(), CliError> {
+    let format = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("csv") => LabelFormat::Csv,
+        _ => LabelFormat::List
+    };
+    let report = hash::load_labels(path, format).map_err(failed(path))?;
+    for issue in report.issues.iter() {
+        eprintln!("warning: {}: {}", path.display(), issue);
+    }
+    for collision in report.collisions.iter() {
+        eprintln!("warning: {}: {}", path.display(), collision);
+    }
+    Ok(())
+}
+
+fn save_patch(diff: &Diff, args: &Args, output: &Path) -> Result<(), CliError> {
+    let format = args.format.unwrap_or_else(|| format_for(output));
+    if args.embed_labels {
+        diff.save_labeled(output, format, hash::Labels::global())
+    } else {
+        diff.save_as(output, format)
+    }
+    .map_err(failed(output))
+}
+
+fn run(args: Args) -> Result<(), CliError> {
+    match args.command {
+        Command::Help => {
+            print!("{}", USAGE);
+            return Ok(());
+        },
+        Command::Version => {
+            println!("prcx {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        },
+        _ => {}
+    }
+    for path in args.labels.iter() {
+        load_labels(path)?;
+    }
+
+    // parse_args makes sure that the commands which write a new file have an output
+    let output = || args.output.as_deref().ok_or_else(|| CliError::Usage("no output was given".to_string()));
+
+    match &args.command {
+        Command::Diff { vanilla, modded } if vanilla.is_dir() && modded.is_dir() => {
+            let output = output()?;
+            let format = args.format.unwrap_or(Format::Binary);
+            let report = if args.embed_labels {
+                tree::diff_tree_labeled(vanilla, modded, output, format, Labels::global())
+            } else {
+                tree::diff_tree(vanilla, modded, output, format)
+            }
+            .map_err(failed(output))?;
+            if !report.failed.is_empty() {
+                return Err(CliError::Failed(report.to_string()));
+            } else if !report.is_complete() {
+                return Err(CliError::Incomplete(report.to_string()));
+            }
+            println!("{}", report);
+        },
+        Command::Diff { vanilla, modded } => {
+            let source = prc::open(vanilla).map_err(failed(vanilla))?;
+            let modded_params = prc::open(modded).map_err(failed(modded))?;
+            if args.strict {
+                let diff = Diff::try_generate(&source, &modded_params).map_err(failed(modded))?;
+                save_patch(&diff, &args, output()?)?;
+                println!("wrote {} changed params to {}", diff.0.len(), output()?.display());
+            } else {
+                let report = Diff::generate(&source, &modded_params);
+                save_patch(&report.diff, &args, output()?)?;
+                if !report.is_complete() {
+                    return Err(CliError::Incomplete(format!("{}: {}", modded.display(), report)));
+                }
+                println!("wrote {} changed params to {}", report.diff.0.len(), output()?.display());
+            }
+        },
+        Command::Apply { patch, input } => {
+            let diff = Diff::load(patch).map_err(failed(patch))?;
+            let mut params = prc::open(input).map_err(failed(input))?;
+            if args.strict {
+                diff.try_apply(&mut params).map_err(failed(patch))?;
+                prc::save(output()?, &params).map_err(failed(output()?))?;
+                println!("applied every entry of {}", patch.display());
+            } else {
+                let report = diff.apply(&mut params);
+                prc::save(output()?, &params).map_err(failed(output()?))?;
+                if !report.is_complete() {
+                    return Err(CliError::Incomplete(format!("{}: {}", patch.display(), report)));
+                }
+                println!("{}", report);
+            }
+        },
+        Command::Convert { patch } => {
+            let diff = Diff::load(patch).map_err(failed(patch))?;
+            save_patch(&diff, &args, output()?)?;
+        },
+        Command::Batch { vanilla, mods } => {
+            let output = output()?;
+            let report = tree::apply_tree(vanilla, mods, output).map_err(failed(output))?;
+            if !report.failed.is_empty() {
+                return Err(CliError::Failed(report.to_string()));
+            } else if !report.is_complete() {
+                return Err(CliError::Incomplete(report.to_string()));
+            }
+            println!("{}", report);
+        },
+        Command::Show { file, key } => {
+            let params = prc::open(file).map_err(failed(file))?;
+            let key = match key {
+                Some(key) => key,
+                None => {
+                    for (hash, param) in params.0.iter() {
+                        let name = PrcKey::from(PrcKeyType::StructField(*hash)).to_string();
+                        print!("{}", dump::to_tree_string(&name, param, Labels::global()));
+                    }
+                    return Ok(());
+                }
+            };
+            for (key, param) in lookup(&params, key).map_err(failed(file))? {
+                print!("{}", dump::to_tree_string(&key.to_string(), param, Labels::global()));
+            }
+        },
+        Command::Get { file, key } => {
+            let params = prc::open(file).map_err(failed(file))?;
+            let found = lookup(&params, key).map_err(failed(file))?;
+            // A single param is printed on its own so that scripts can use it as it is
+            let single = key.parse::<PrcKey>().map(|key| key.is_concrete()).unwrap_or(false);
+            for (key, param) in found {
+                let value = match dump::write_value(param, Labels::global()) {
+                    Some(value) => value,
+                    None if single => {
+                        print!("{}", dump::to_tree_string(&key.to_string(), param, Labels::global()));
+                        continue;
+                    },
+                    None => container(param)
+                };
+                if single {
+                    println!("{}", value);
+                } else {
+                    println!("{} = {}", key, value);
+                }
+            }
+        },
+        Command::Set { file, key, value } => {
+            let mut params = prc::open(file).map_err(failed(file))?;
+            let key: PrcKey = key.parse().map_err(|e| CliError::Failed(format!("`{}`: {}", key, e)))?;
+            let keys = params.expand(&key).map_err(failed(file))?;
+            if keys.is_empty() {
+                return Err(CliError::Failed(format!("{}: `{}` matches no params", file.display(), key)));
+            }
+            // Plain numbers take the type of each param they replace
+            let mut entries = Vec::with_capacity(keys.len());
+            for key in keys {
+                let like = params.get(&key).map_err(failed(file))?;
+                let value = Diff::parse_value_as(value, like).ok_or_else(|| CliError::Failed(format!("`{}` is not a valid value", value)))?;
+                entries.push((key, value));
+            }
+            let count = entries.len();
+            Diff(entries).try_apply(&mut params).map_err(failed(file))?;
+            let output = args.output.as_deref().unwrap_or(file);
+            prc::save(output, &params).map_err(failed(output))?;
+            println!("set {} params in {}", count, output.display());
+        },
+        Command::Help | Command::Version => {}
+    }
+    Ok(())
+}
+
+// Every param that a key or pattern covers
+fn lookup<'a>(params: &'a ParamStruct, key: &str) -> Result<Vec<(PrcKey, &'a ParamKind)>, prcx::Error> {
+    let key: PrcKey = key.parse()?;
+    let mut found = vec![];
+    for key in params.expand(&key)? {
+        let param = params.get(&key)?;
+        found.push((key, param));
+    }
+    Ok(found)
+}
+
+fn container(param: &ParamKind) -> String {
+    match param {
+        ParamKind::Struct(s) => format!("struct({})", s.0.len()),
+        ParamKind::List(l) => format!("list({})", l.0.len()),
+        _ => String::new()
+    }
+}
+
+fn main() {
+    let result = parse_args(env::args().skip(1)).and_then(run);
+    if let Err(error) = result {
+        eprintln!("prcx: {}", error);
+        process::exit(error.exit_code());
+    }
+}
+
+#[cfg(test)]
+fn args(line: &str) -> Result<Args, CliError> {
+    parse_args(line.split_whitespace().map(str::to_string))
+}
+
+#[test]
+fn parse_args_test() {
+    let parsed = args("--labels a.csv diff vanilla.prc modded.prc -o out.prcx --embed-labels --labels b.txt").unwrap();
+    assert_eq!(parsed.command, Command::Diff { vanilla: "vanilla.prc".into(), modded: "modded.prc".into() });
+    assert_eq!(parsed.labels, vec![PathBuf::from("a.csv"), PathBuf::from("b.txt")]);
+    assert_eq!(parsed.output, Some("out.prcx".into()));
+    assert!(parsed.embed_labels && !parsed.strict);
+
+    let parsed = args("convert patch.prcx --format json -o patch.txt").unwrap();
+    assert_eq!((parsed.command, parsed.format), (Command::Convert { patch: "patch.prcx".into() }, Some(Format::Json)));
+    assert_eq!(format_for(Path::new("patch.txt")), Format::Text);
+    assert_eq!(format_for(Path::new("patch.prctxt")), Format::Text);
+    assert_eq!(args("apply --help").unwrap().command, Command::Help);
+
+    assert_eq!(args("show fighter_param.prc").unwrap().command, Command::Show { file: "fighter_param.prc".into(), key: None });
+    let parsed = args("set fighter_param.prc table[0].speed -1.5").unwrap();
+    assert_eq!(parsed.command, Command::Set { file: "fighter_param.prc".into(), key: "table[0].speed".into(), value: "-1.5".into() });
+    assert_eq!(parsed.output, None);
+    let parsed = args("batch vanilla mod_a mod_b -o out").unwrap();
+    assert_eq!(parsed.command, Command::Batch { vanilla: "vanilla".into(), mods: vec!["mod_a".into(), "mod_b".into()] });
+    assert_eq!(args("get file.prc -- -key").unwrap().command, Command::Get { file: "file.prc".into(), key: "-key".into() });
+
+    for line in ["", "apply patch.prcx -o out.prc", "diff a b c -o out", "convert patch.prcx", "convert a -o", "fix a -o b", "convert a -o b --format yaml", "convert a -o b --bogus", "get file.prc", "set file.prc key", "batch vanilla -o out"].iter() {
+        assert_eq!(args(line).err().map(|e| e.exit_code()), Some(EXIT_USAGE), "{}", line);
+    }
+}
+
+#[test]
+fn run_test() {
+    use prcx::prc::{hash40::to_hash40, ParamList};
+
+    let dir = env::temp_dir().join(format!("prcx_cli_{}", process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let run_args = |line: &[&str]| parse_args(line.iter().map(|arg| arg.to_string())).and_then(run);
+
+    hash::add_hashes(vec!["cli_speed", "cli_list", "cli_count"]);
+    let vanilla = ParamStruct(vec![
+        (to_hash40("cli_speed"), ParamKind::Float(1.0)),
+        (to_hash40("cli_list"), ParamKind::List(ParamList(vec![]))),
+        (to_hash40("cli_count"), ParamKind::U8(1))
+    ]);
+    prc::save(path("vanilla.prc"), &vanilla).unwrap();
+
+    // Labels are only read once there is a command to run
+    run_args(&["--labels", &path("missing.csv"), "--help"]).unwrap();
+    assert_eq!(run_args(&["--labels", &path("missing.csv"), "get", &path("vanilla.prc"), "cli_speed"]).unwrap_err().exit_code(), EXIT_FAILURE);
+
+    run_args(&["set", &path("vanilla.prc"), "cli_speed", "2.5", "-o", &path("modded.prc")]).unwrap();
+    assert_eq!(prc::open(path("modded.prc")).unwrap().get(&"cli_speed".parse().unwrap()), Ok(&ParamKind::Float(2.5)));
+    for value in ["hash40(cli", "2.5\ncli_speed = 3.5"].iter() {
+        assert_eq!(run_args(&["set", &path("modded.prc"), "cli_speed", value]).unwrap_err().exit_code(), EXIT_FAILURE, "{}", value);
+    }
+    assert_eq!(run_args(&["set", &path("modded.prc"), "cli_list[*]", "1u8"]).unwrap_err().exit_code(), EXIT_FAILURE);
+    assert_eq!(prc::open(path("modded.prc")).unwrap().get(&"cli_speed".parse().unwrap()), Ok(&ParamKind::Float(2.5)));
+
+    // Plain numbers take the type of the field they are set on
+    run_args(&["set", &path("vanilla.prc"), "cli_count", "5", "-o", &path("plain.prc")]).unwrap();
+    run_args(&["set", &path("plain.prc"), "cli_speed", "3"]).unwrap();
+    let plain = prc::open(path("plain.prc")).unwrap();
+    assert_eq!(plain.get(&"cli_count".parse().unwrap()), Ok(&ParamKind::U8(5)));
+    assert_eq!(plain.get(&"cli_speed".parse().unwrap()), Ok(&ParamKind::Float(3.0)));
+    assert_eq!(run_args(&["set", &path("plain.prc"), "cli_count", "5i8"]).unwrap_err().exit_code(), EXIT_FAILURE);
+
+    run_args(&["diff", &path("vanilla.prc"), &path("modded.prc"), "-o", &path("patch.prctxt")]).unwrap();
+    assert_eq!(std::fs::read_to_string(path("patch.prctxt")).unwrap(), "cli_speed = 2.5f32\n");
+    run_args(&["apply", &path("patch.prctxt"), &path("vanilla.prc"), "-o", &path("applied.prc")]).unwrap();
+    assert_eq!(prc::open(path("applied.prc")).unwrap(), prc::open(path("modded.prc")).unwrap());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,561 @@
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    str::FromStr
+};
+
+use prc::{
+    ParamKind,
+    ParamStruct,
+    hash40::Hash40
+};
+
+use crate::{
+    access::ParamAccess,
+    error::{type_name, Error, Result},
+    hash::Labels,
+    key::{
+        PrcKey,
+        PrcKeyType
+    }
+};
+
+// The names that `[?type]` filters accept, `int` being any of the integer types
+const TYPE_NAMES: &[&str] = &["bool", "int", "i8", "u8", "i16", "u16", "i32", "u32", "float", "hash40", "string", "list", "struct"];
+
+/// Which struct fields a `.name` step matches
+#[derive(Debug, Clone, PartialEq)]
+enum NameMatcher {
+    /// `.*`, every field
+    Any,
+    /// A label or `0x` hash
+    Hash(Hash40),
+    /// A label with `*` in it, which only matches fields that have a known label
+    Glob(String)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum QueryValue {
+    Bool(bool),
+    Number(f64),
+    Hash(Hash40),
+    Str(String)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    /// `[?float]`, the param has this type
+    Type(&'static str),
+    /// `[?op value]` on the param itself, or `[?field op value]` on a field of a struct
+    Compare {
+        field: Option<Hash40>,
+        op: CompareOp,
+        value: QueryValue
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    /// `.name`
+    Field(NameMatcher),
+    /// `[3]`, `[*]`, `[3..10]` or `[field=value]`, just like in a [`PrcKey`]
+    List(PrcKeyType),
+    /// `[?filter]`
+    Filter(Filter),
+    /// `..` followed by a step, which is then taken from every param at or below the current ones
+    Descendant(Box<Step>)
+}
+
+/// A search over a [`ParamStruct`], written with the same grammar as a [`PrcKey`] plus a few additions:
+///
+/// - `..name` matches fields at any depth, and `..[...]` takes a list or filter step at any depth
+/// - `.*` matches every field, and `*` inside of a label matches any text (`*_frame`)
+/// - `[?float]` keeps params of a type, where `int` stands for any integer type
+/// - `[?> 10]` compares the param itself and `[?kind = mario]` compares a field of a struct, using
+///   `=`, `!=`, `<`, `<=`, `>` or `>=` against a number, `true`/`false`, a quoted string or a label
+///
+/// For example `..*_frame[?float]` finds every float field whose label ends in `_frame`, and
+/// `fighter_param_table[*][?fighter_kind = fighter_kind_mario]` finds the entries for Mario.
+/// Field names can be quoted just like in a key, which also turns off `*` matching. A leading `$` is allowed and ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    steps: Vec<Step>
+}
+
+// A param that a query is currently looking at. The root of the search isn't a ParamKind.
+#[derive(Clone, Copy)]
+enum Node<'a> {
+    Root(&'a ParamStruct),
+    Param(&'a ParamKind)
+}
+
+impl<'a> Node<'a> {
+    fn as_struct(self) -> Option<&'a ParamStruct> {
+        match self {
+            Node::Root(s) | Node::Param(ParamKind::Struct(s)) => Some(s),
+            _ => None
+        }
+    }
+
+    fn param(self) -> Option<&'a ParamKind> {
+        match self {
+            Node::Param(param) => Some(param),
+            Node::Root(_) => None
+        }
+    }
+
+    fn type_name(self) -> &'static str {
+        match self {
+            Node::Root(_) => "struct",
+            Node::Param(param) => type_name(param)
+        }
+    }
+
+    fn get(self, key: &PrcKey) -> Option<&'a ParamKind> {
+        match self {
+            Node::Root(s) => s.get(key).ok(),
+            Node::Param(param) => param.get(key).ok()
+        }
+    }
+
+    fn expand(self, key: &PrcKey) -> Vec<PrcKey> {
+        let keys = match self {
+            Node::Root(s) => s.expand(key),
+            Node::Param(param) => param.expand(key)
+        };
+        keys.unwrap_or_default()
+    }
+
+    // Every direct child along with the segment that leads to it
+    fn children(self) -> Vec<(PrcKeyType, &'a ParamKind)> {
+        match self {
+            Node::Root(s) | Node::Param(ParamKind::Struct(s)) => s.0
+                .iter()
+                .map(|(hash, param)| (PrcKeyType::StructField(*hash), param))
+                .collect(),
+            Node::Param(ParamKind::List(l)) => l.0
+                .iter()
+                .enumerate()
+                .map(|(idx, param)| (PrcKeyType::ListIndex(idx), param))
+                .collect(),
+            Node::Param(_) => vec![]
+        }
+    }
+}
+
+// Matches `*` against any run of characters, which is all that field globs need
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (idx, part) in parts.iter().enumerate() {
+        if idx == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false
+        }
+    }
+    // No `*` at all, so the text has to be the pattern exactly
+    rest.is_empty()
+}
+
+impl NameMatcher {
+    fn parse(name: &str, labels: &Labels) -> Self {
+        if name == "*" {
+            NameMatcher::Any
+        } else if name.contains('*') {
+            NameMatcher::Glob(name.to_string())
+        } else {
+            NameMatcher::Hash(PrcKey::hash_segment(name, labels))
+        }
+    }
+
+    fn matches(&self, hash: Hash40, labels: &Labels) -> bool {
+        match self {
+            NameMatcher::Any => true,
+            NameMatcher::Hash(other) => hash == *other,
+            NameMatcher::Glob(pattern) => labels.try_get(hash).map(|label| glob_match(pattern, &label)).unwrap_or(false)
+        }
+    }
+}
+
+impl QueryValue {
+    fn parse(s: &str, labels: &Labels) -> Option<Self> {
+        if let Some(quoted) = s.strip_prefix('"') {
+            return PrcKey::unquote(quoted).map(QueryValue::Str);
+        }
+        match s {
+            "true" => return Some(QueryValue::Bool(true)),
+            "false" => return Some(QueryValue::Bool(false)),
+            _ => {}
+        }
+        // Only things that look like numbers are numbers, since labels like `inf` would parse as floats too
+        if s.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+' || c == '.') {
+            return s.parse().ok().map(QueryValue::Number);
+        }
+        Some(QueryValue::Hash(PrcKey::hash_segment(s, labels)))
+    }
+
+    // How the param compares to this value, or `None` if they can't be compared at all
+    fn compare(&self, param: &ParamKind) -> Option<Ordering> {
+        let number = match param {
+            ParamKind::I8(x) => Some(*x as f64),
+            ParamKind::U8(x) => Some(*x as f64),
+            ParamKind::I16(x) => Some(*x as f64),
+            ParamKind::U16(x) => Some(*x as f64),
+            ParamKind::I32(x) => Some(*x as f64),
+            ParamKind::U32(x) => Some(*x as f64),
+            _ => None
+        };
+        match (self, param) {
+            // Floats are compared as f32 so that `[?= 0.1]` matches a float that was written as 0.1
+            (QueryValue::Number(value), ParamKind::Float(other)) => other.partial_cmp(&(*value as f32)),
+            (QueryValue::Number(value), _) => number?.partial_cmp(value),
+            (QueryValue::Bool(value), ParamKind::Bool(other)) => Some(other.cmp(value)),
+            (QueryValue::Hash(value), ParamKind::Hash(other)) => Some(other.0.cmp(&value.0)),
+            (QueryValue::Str(value), ParamKind::Str(other)) => Some(other.as_str().cmp(value)),
+            _ => None
+        }
+    }
+}
+
+impl CompareOp {
+    fn test(self, ordering: Ordering) -> bool {
+        match self {
+            CompareOp::Eq => ordering == Ordering::Equal,
+            CompareOp::Ne => ordering != Ordering::Equal,
+            CompareOp::Lt => ordering == Ordering::Less,
+            CompareOp::Le => ordering != Ordering::Greater,
+            CompareOp::Gt => ordering == Ordering::Greater,
+            CompareOp::Ge => ordering != Ordering::Less
+        }
+    }
+}
+
+impl Filter {
+    fn parse(source: &str, at: &str, inner: &str, labels: &Labels) -> Result<Self> {
+        // The first operator outside of a quoted string, trying the two character ones first
+        let mut op = None;
+        let mut quoted = false;
+        let mut escaped = false;
+        for (idx, c) in inner.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                _ => {}
+            }
+            if quoted || c == '"' {
+                continue;
+            }
+            let two = inner.get(idx..idx + 2).unwrap_or("");
+            let found = match (two, c) {
+                ("!=", _) => Some((CompareOp::Ne, 2)),
+                ("<=", _) => Some((CompareOp::Le, 2)),
+                (">=", _) => Some((CompareOp::Ge, 2)),
+                (_, '=') => Some((CompareOp::Eq, 1)),
+                (_, '<') => Some((CompareOp::Lt, 1)),
+                (_, '>') => Some((CompareOp::Gt, 1)),
+                _ => None
+            };
+            if let Some((found, len)) = found {
+                op = Some((idx, found, len));
+                break;
+            }
+        }
+
+        let (idx, op, len) = match op {
+            Some(op) => op,
+            None => {
+                let name = inner.trim();
+                return TYPE_NAMES
+                    .iter()
+                    .find(|x| **x == name)
+                    .map(|x| Filter::Type(x))
+                    .ok_or_else(|| Error::parse_at(source, at, format!("`{}` is neither a type nor a comparison", name)));
+            }
+        };
+
+        let field = inner[..idx].trim();
+        let value = inner[idx + len..].trim();
+        let field = match field.strip_prefix('"') {
+            Some(quoted) => {
+                let label = PrcKey::unquote(quoted)
+                    .ok_or_else(|| Error::parse_at(source, at, format!("malformed field name `{}` in filter", field)))?;
+                Some(labels.hash_label(&label))
+            },
+            None if field.is_empty() => None,
+            None => Some(PrcKey::hash_segment(field, labels))
+        };
+        let value = QueryValue::parse(value, labels)
+            .filter(|_| !value.is_empty())
+            .ok_or_else(|| Error::parse_at(source, at, format!("malformed value `{}` in filter", value)))?;
+        Ok(Filter::Compare { field, op, value })
+    }
+
+    fn matches(&self, node: Node<'_>) -> bool {
+        match self {
+            Filter::Type("int") => matches!(
+                node.param(),
+                Some(ParamKind::I8(_)) | Some(ParamKind::U8(_)) | Some(ParamKind::I16(_)) |
+                Some(ParamKind::U16(_)) | Some(ParamKind::I32(_)) | Some(ParamKind::U32(_))
+            ),
+            Filter::Type(name) => node.type_name() == *name,
+            Filter::Compare { field: None, op, value } => node
+                .param()
+                .and_then(|param| value.compare(param))
+                .map(|ordering| op.test(ordering))
+                .unwrap_or(false),
+            Filter::Compare { field: Some(field), op, value } => node
+                .as_struct()
+                .map(|s| s.0
+                    .iter()
+                    .filter(|(hash, _)| hash == field)
+                    .any(|(_, param)| value.compare(param).map(|ordering| op.test(ordering)).unwrap_or(false)))
+                .unwrap_or(false)
+        }
+    }
+}
+
+impl Step {
+    fn apply<'a>(&self, key: &PrcKey, node: Node<'a>, labels: &Labels, out: &mut Vec<(PrcKey, Node<'a>)>) {
+        match self {
+            Step::Field(matcher) => {
+                if let Some(s) = node.as_struct() {
+                    for (hash, param) in s.0.iter().filter(|(hash, _)| matcher.matches(*hash, labels)) {
+                        let mut key = key.clone();
+                        key.push(PrcKeyType::StructField(*hash));
+                        out.push((key, Node::Param(param)));
+                    }
+                }
+            },
+            Step::List(segment) => {
+                for child in node.expand(&PrcKey::from(segment.clone())) {
+                    if let Some(param) = node.get(&child) {
+                        out.push((key.join(&child), Node::Param(param)));
+                    }
+                }
+            },
+            Step::Filter(filter) => {
+                if filter.matches(node) {
+                    out.push((key.clone(), node));
+                }
+            },
+            Step::Descendant(step) => {
+                let mut stack = vec![(key.clone(), node)];
+                while let Some((key, node)) = stack.pop() {
+                    step.apply(&key, node, labels, out);
+                    // Pushed in reverse so that children are visited in order
+                    for (segment, param) in node.children().into_iter().rev() {
+                        let mut key = key.clone();
+                        key.push(segment);
+                        stack.push((key, Node::Param(param)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Query {
+    /// Like [`str::parse`], with the labels in the query harvested into `labels` when it is
+    /// [harvesting](Labels::set_harvesting) rather than into the global table
+    pub fn parse_with(source: &str, labels: &Labels) -> Result<Self> {
+        let mut steps = vec![];
+        let mut rest = source.trim().strip_prefix('$').unwrap_or_else(|| source.trim());
+        let mut first = true;
+        while !rest.is_empty() {
+            let at = rest;
+            let descendant = if let Some(next) = rest.strip_prefix("..") {
+                rest = next;
+                true
+            } else {
+                if let Some(next) = rest.strip_prefix('.') {
+                    rest = next;
+                } else if !first && !rest.starts_with('[') {
+                    return Err(Error::parse_at(source, rest, "expected `.`, `..` or `[`"));
+                }
+                false
+            };
+            first = false;
+
+            let step = if let Some(inner) = rest.strip_prefix('[') {
+                let end = PrcKey::find_list_end(inner)
+                    .ok_or_else(|| Error::parse_at(source, at, "`[` was not terminated"))?;
+                rest = &inner[end + 1..];
+                match inner[..end].strip_prefix('?') {
+                    Some(filter) => Step::Filter(Filter::parse(source, at, filter, labels)?),
+                    None => Step::List(PrcKey::parse_list_segment(source, at, &inner[..end], labels)?)
+                }
+            } else if let Some(quoted) = rest.strip_prefix('"') {
+                let (label, next) = PrcKey::split_quoted(quoted)
+                    .ok_or_else(|| Error::parse_at(source, rest, "quoted field name was not terminated"))?;
+                rest = next;
+                Step::Field(NameMatcher::Hash(labels.hash_label(&label)))
+            } else {
+                let name_end = rest.find(['.', '[']).unwrap_or(rest.len());
+                let (name, next) = rest.split_at(name_end);
+                if name.is_empty() {
+                    return Err(Error::parse_at(source, rest, "expected a field name"));
+                }
+                rest = next;
+                Step::Field(NameMatcher::parse(name, labels))
+            };
+
+            steps.push(if descendant { Step::Descendant(Box::new(step)) } else { step });
+        }
+
+        if steps.is_empty() {
+            return Err(Error::parse(1, 1, "the query is empty"));
+        }
+        Ok(Self { steps })
+    }
+
+    /// Every param that the query matches along with its concrete key, in the order they appear in `params`
+    pub fn run<'a>(&self, params: &'a ParamStruct) -> Vec<(PrcKey, &'a ParamKind)> {
+        self.run_with(params, Labels::global())
+    }
+
+    /// Like [`run`](Self::run), with field globs matched against the labels in `labels` instead of the global table
+    pub fn run_with<'a>(&self, params: &'a ParamStruct, labels: &Labels) -> Vec<(PrcKey, &'a ParamKind)> {
+        let mut nodes = vec![(PrcKey::new(), Node::Root(params))];
+        for step in self.steps.iter() {
+            let mut next = vec![];
+            for (key, node) in nodes.iter() {
+                step.apply(key, *node, labels, &mut next);
+            }
+            // Descending from nested params can reach the same param twice
+            if let Step::Descendant(_) = step {
+                let mut seen = HashSet::new();
+                next.retain(|(key, _)| seen.insert(key.clone()));
+            }
+            nodes = next;
+        }
+
+        nodes
+            .into_iter()
+            .filter_map(|(key, node)| node.param().map(|param| (key, param)))
+            .collect()
+    }
+}
+
+impl FromStr for Query {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse_with(s, Labels::global())
+    }
+}
+
+/// Parses `query` and runs it over `params`. See [`Query`] for the syntax.
+pub fn query<'a>(params: &'a ParamStruct, query: &str) -> Result<Vec<(PrcKey, &'a ParamKind)>> {
+    query_with(params, query, Labels::global())
+}
+
+/// Like [`query`], with `labels` used instead of the global table
+pub fn query_with<'a>(params: &'a ParamStruct, query: &str, labels: &Labels) -> Result<Vec<(PrcKey, &'a ParamKind)>> {
+    Ok(Query::parse_with(query, labels)?.run_with(params, labels))
+}
+
+#[cfg(test)]
+use prc::hash40::to_hash40;
+
+#[cfg(test)]
+fn test_params() -> ParamStruct {
+    use prc::ParamList;
+
+    crate::hash::add_hashes(vec![
+        "query_table", "query_kind", "query_mario", "query_luigi", "query_landing_frame",
+        "query_jump_frame", "query_speed", "query_nested", "query_name"
+    ]);
+    let entry = |kind: &str, speed: f32| ParamKind::Struct(ParamStruct(vec![
+        (to_hash40("query_kind"), ParamKind::Hash(to_hash40(kind))),
+        (to_hash40("query_landing_frame"), ParamKind::Float(4.0)),
+        (to_hash40("query_jump_frame"), ParamKind::U8(3)),
+        (to_hash40("query_speed"), ParamKind::Float(speed)),
+    ]));
+    ParamStruct(vec![
+        (to_hash40("query_table"), ParamKind::List(ParamList(vec![entry("query_mario", 0.1), entry("query_luigi", 2.0)]))),
+        (to_hash40("query_nested"), ParamKind::Struct(ParamStruct(vec![
+            (to_hash40("query_landing_frame"), ParamKind::Float(8.0)),
+            (to_hash40("query_name"), ParamKind::Str("query \"name\"".to_string())),
+        ]))),
+    ])
+}
+
+#[cfg(test)]
+fn keys(params: &ParamStruct, s: &str) -> Vec<String> {
+    match query(params, s) {
+        Ok(found) => found.into_iter().map(|(key, _)| key.to_string()).collect(),
+        Err(e) => panic!("Failed to parse query `{}`: {}", s, e)
+    }
+}
+
+#[test]
+fn query_test() {
+    let params = test_params();
+
+    assert_eq!(keys(&params, "..*_frame[?float]"), vec![
+        "query_table[0].query_landing_frame",
+        "query_table[1].query_landing_frame",
+        "query_nested.query_landing_frame"
+    ]);
+    assert_eq!(keys(&params, "$.query_table[*][?query_kind = query_luigi].query_speed"), vec!["query_table[1].query_speed"]);
+    assert_eq!(keys(&params, "query_table[*].query_speed[?> 1.5]"), vec!["query_table[1].query_speed"]);
+    assert_eq!(keys(&params, "..[?query_speed <= 2]"), vec!["query_table[0]", "query_table[1]"]);
+    assert_eq!(keys(&params, "query_table[query_kind=query_mario].*[?int]"), vec!["query_table[0].query_jump_frame"]);
+    assert_eq!(keys(&params, "..query_nested..[?= \"query \\\"name\\\"\"]"), vec!["query_nested.query_name"]);
+    assert_eq!(keys(&params, "query_nested[?query_name = \"query \\\"name\\\"\"]"), vec!["query_nested"]);
+
+    assert_eq!(keys(&params, "query_nested.*").len(), 2);
+    assert_eq!(keys(&params, "..query_speed[?= 0.1]"), vec!["query_table[0].query_speed"]);
+    assert!(keys(&params, "query_missing..query_speed").is_empty());
+
+    // Quoted field names can have escaped quotes and operators in them
+    crate::hash::add_hashes(vec!["query_odd"]);
+    let odd = ParamStruct(vec![(to_hash40("query_odd"), ParamKind::Struct(ParamStruct(vec![(to_hash40("a\"<b"), ParamKind::U8(1))])))]);
+    assert_eq!(keys(&odd, "query_odd[?\"a\\\"<b\" = 1]"), vec!["query_odd"]);
+}
+
+#[test]
+fn query_labels_test() {
+    let labels = Labels::new();
+    labels.add_all(vec!["query_local_frame", "query_local_speed"]);
+    let params = ParamStruct(vec![
+        (to_hash40("query_local_frame"), ParamKind::U8(1)),
+        (to_hash40("query_local_speed"), ParamKind::U8(2)),
+    ]);
+    let found = |s: &str| query_with(&params, s, &labels).unwrap().into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+    assert_eq!(found("*_frame"), vec![PrcKey::field("query_local_frame").build()]);
+    assert!(query(&params, "*_frame").unwrap().is_empty());
+
+    // Quoted names are harvested like plain ones
+    labels.set_harvesting(true);
+    Query::parse_with("\"query quoted\"[?\"query filter\" = 1]", &labels).unwrap();
+    labels.set_harvesting(false);
+    assert_eq!(labels.try_get(to_hash40("query quoted")).as_deref(), Some("query quoted"));
+    assert_eq!(labels.try_get(to_hash40("query filter")).as_deref(), Some("query filter"));
+}
+
+#[test]
+fn malformed_query_test() {
+    for s in ["", "query_table[*", "query_table[?frobnicate]", "query_table[?query_kind = ]", "query_table[0]query_speed"].iter() {
+        assert!(s.parse::<Query>().is_err(), "`{}` was accepted", s);
+    }
+}
@@ -0,0 +1,445 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf}
+};
+
+use crate::{
+    diff::Diff,
+    error::{Error, Result},
+    format::Format,
+    hash::Labels
+};
+
+#[cfg(test)]
+use prc::{
+    ParamKind,
+    ParamStruct,
+    hash40::to_hash40
+};
+
+/// The extensions of the patch files that [`apply_tree`] picks up
+pub const PATCH_EXTENSIONS: &[&str] = &["prcx", "prctxt"];
+
+/// What [`apply_tree`] did to one param file
+#[derive(Debug)]
+pub struct FileReport {
+    /// The path of the param file, relative to the roots
+    pub path: PathBuf,
+    /// Every patch that was applied to it, in the order they were applied
+    pub patches: Vec<PathBuf>,
+    /// How many params were changed, over all of the patches
+    pub applied: usize,
+    /// The entries of the patches that didn't fit, see [`ApplyReport`](crate::diff::ApplyReport)
+    pub skipped: Vec<Error>
+}
+
+/// What [`apply_tree`] did
+#[derive(Debug, Default)]
+pub struct ApplyTreeReport {
+    /// Every param file that was written, sorted by path
+    pub files: Vec<FileReport>,
+    /// Patches that couldn't be read, and vanilla param files that couldn't be read or don't exist
+    pub failed: Vec<(PathBuf, Error)>
+}
+
+impl ApplyTreeReport {
+    /// Whether every patch was read and every entry of them was applied
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty() && self.files.iter().all(|file| file.skipped.is_empty())
+    }
+}
+
+impl fmt::Display for ApplyTreeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let skipped: usize = self.files.iter().map(|file| file.skipped.len()).sum();
+        write!(f, "patched {} files, skipped {} entries, {} failed", self.files.len(), skipped, self.failed.len())?;
+        for file in self.files.iter() {
+            write!(
+                f,
+                "\n  {}: applied {} params from {} patches, skipped {} entries",
+                file.path.display(),
+                file.applied,
+                file.patches.len(),
+                file.skipped.len()
+            )?;
+            for error in file.skipped.iter() {
+                write!(f, "\n    {}", error)?;
+            }
+        }
+        for (path, error) in self.failed.iter() {
+            write!(f, "\n  {}: {}", path.display(), error)?;
+        }
+        Ok(())
+    }
+}
+
+// The paths of every file under `dir`, relative to `root` and sorted so that runs are repeatable
+fn walk(root: &Path, dir: &Path, found: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            walk(root, &path, found)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            found.push(relative.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension().and_then(|ext| ext.to_str()).map(|ext| extensions.contains(&ext)).unwrap_or(false)
+}
+
+/// Applies every patch in the `mods` trees to the param file at the same place in the `vanilla` tree, and
+/// writes the patched files to the same place under `output`.
+///
+/// A patch is matched to its param file by its path relative to its mod root, with the extension
+/// swapped, so `fighter/common/param/fighter_param.prcx` patches `fighter/common/param/fighter_param.prc`.
+/// Patches are read in any format with [`Diff::load`], and files with more than one patch get them in the
+/// order the mod roots were given, so later mods win. Only the param files that were patched are written.
+///
+/// Problems with single files are collected in the report instead of stopping the rest of the tree.
+/// Only failing to read a mod tree or to write the output is an error.
+pub fn apply_tree<V: AsRef<Path>, M: AsRef<Path>, O: AsRef<Path>>(vanilla: V, mods: &[M], output: O) -> Result<ApplyTreeReport> {
+    let (vanilla, output) = (vanilla.as_ref(), output.as_ref());
+    let mut targets: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for root in mods.iter() {
+        let root = root.as_ref();
+        let mut found = vec![];
+        walk(root, root, &mut found)?;
+        for relative in found.into_iter().filter(|path| has_extension(path, PATCH_EXTENSIONS)) {
+            targets.entry(relative.with_extension("prc")).or_default().push(root.join(relative));
+        }
+    }
+
+    let mut report = ApplyTreeReport::default();
+    for (relative, patches) in targets {
+        let mut params = match prc::open(vanilla.join(&relative)) {
+            Ok(params) => params,
+            Err(e) => {
+                report.failed.push((vanilla.join(&relative), e.into()));
+                continue;
+            }
+        };
+
+        let mut file = FileReport { path: relative, patches: vec![], applied: 0, skipped: vec![] };
+        for patch in patches {
+            match Diff::load(&patch) {
+                Ok(diff) => {
+                    let applied = diff.apply(&mut params);
+                    file.applied += applied.applied;
+                    file.skipped.extend(applied.skipped);
+                    file.patches.push(patch);
+                },
+                Err(e) => report.failed.push((patch, e))
+            }
+        }
+        if file.patches.is_empty() {
+            continue;
+        }
+
+        let path = output.join(&file.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        prc::save(path, &params)?;
+        report.files.push(file);
+    }
+    Ok(report)
+}
+
+/// What [`diff_tree`] found
+#[derive(Debug, Default)]
+pub struct DiffTreeReport {
+    /// Every patch that was written, relative to the output, with how many params it changes
+    pub written: Vec<(PathBuf, usize)>,
+    /// Param files that got a patch that leaves some of their changes out, with why each one was,
+    /// see [`GenerateReport`](crate::diff::GenerateReport)
+    pub partial: Vec<(PathBuf, Vec<Error>)>,
+    /// Param files that are the same in both trees, so they got no patch
+    pub identical: Vec<PathBuf>,
+    /// Param files that differ only in ways that a patch can't hold, such as lists that grew, fields that
+    /// were added or structs that became values, so they got no patch either
+    pub unpatchable: Vec<(PathBuf, Vec<Error>)>,
+    /// Param files that only the vanilla tree has
+    pub only_vanilla: Vec<PathBuf>,
+    /// Param files that only the modded tree has, which can't be patches since there is nothing to patch
+    pub only_modded: Vec<PathBuf>,
+    /// Param files that couldn't be read or compared
+    pub failed: Vec<(PathBuf, Error)>
+}
+
+impl DiffTreeReport {
+    /// Whether every file was read and every change went into a patch
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty() && self.partial.is_empty() && self.unpatchable.is_empty()
+    }
+}
+
+impl fmt::Display for DiffTreeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "wrote {} patches, {} of them partial, {} files identical, {} differ without a patch, {} only in vanilla, {} only in modded, {} failed",
+            self.written.len(),
+            self.partial.len(),
+            self.identical.len(),
+            self.unpatchable.len(),
+            self.only_vanilla.len(),
+            self.only_modded.len(),
+            self.failed.len()
+        )?;
+        for (path, changed) in self.written.iter() {
+            write!(f, "\n  {}: {} changed params", path.display(), changed)?;
+        }
+        for (path, skipped) in self.partial.iter() {
+            write!(f, "\n  {}: left {} changes out of its patch", path.display(), skipped.len())?;
+            for error in skipped.iter() {
+                write!(f, "\n    {}", error)?;
+            }
+        }
+        for (path, skipped) in self.unpatchable.iter() {
+            write!(f, "\n  {}: differs only in ways that a patch can't hold", path.display())?;
+            for error in skipped.iter() {
+                write!(f, "\n    {}", error)?;
+            }
+        }
+        for path in self.only_vanilla.iter() {
+            write!(f, "\n  {}: only in vanilla", path.display())?;
+        }
+        for path in self.only_modded.iter() {
+            write!(f, "\n  {}: only in modded", path.display())?;
+        }
+        for (path, error) in self.failed.iter() {
+            write!(f, "\n  {}: {}", path.display(), error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares every `.prc` in the `modded` tree with the one at the same place in the `vanilla` tree, and
+/// writes a patch for each one that differs to the same place under `output`. This is the reverse of
+/// [`apply_tree`].
+///
+/// Patches are written in `format`, as `.prctxt` for [`Format::Text`] and `.prcx` for everything else.
+/// Identical files are skipped, and files that only one of the trees has are listed in the report. So
+/// are the changes that a patch can't hold, see [`Diff::generate`].
+pub fn diff_tree<V: AsRef<Path>, M: AsRef<Path>, O: AsRef<Path>>(vanilla: V, modded: M, output: O, format: Format) -> Result<DiffTreeReport> {
+    diff_tree_inner(vanilla.as_ref(), modded.as_ref(), output.as_ref(), format, None)
+}
+
+/// Like [`diff_tree`], with the labels from `labels` that each patch uses embedded in it.
+/// See [`Diff::to_bytes_labeled`].
+pub fn diff_tree_labeled<V: AsRef<Path>, M: AsRef<Path>, O: AsRef<Path>>(
+    vanilla: V,
+    modded: M,
+    output: O,
+    format: Format,
+    labels: &Labels
+) -> Result<DiffTreeReport> {
+    diff_tree_inner(vanilla.as_ref(), modded.as_ref(), output.as_ref(), format, Some(labels))
+}
+
+fn diff_tree_inner(vanilla: &Path, modded: &Path, output: &Path, format: Format, labels: Option<&Labels>) -> Result<DiffTreeReport> {
+    let is_prc = |path: &PathBuf| has_extension(path, &["prc"]);
+    let (mut vanilla_files, mut modded_files) = (vec![], vec![]);
+    walk(vanilla, vanilla, &mut vanilla_files)?;
+    walk(modded, modded, &mut modded_files)?;
+    let vanilla_files: BTreeSet<PathBuf> = vanilla_files.into_iter().filter(is_prc).collect();
+    let modded_files: BTreeSet<PathBuf> = modded_files.into_iter().filter(is_prc).collect();
+
+    let mut report = DiffTreeReport {
+        only_vanilla: vanilla_files.iter().filter(|path| !modded_files.contains(*path)).cloned().collect(),
+        ..Default::default()
+    };
+    let extension = if format == Format::Text { "prctxt" } else { "prcx" };
+    for relative in modded_files {
+        if !vanilla_files.contains(&relative) {
+            report.only_modded.push(relative);
+            continue;
+        }
+
+        let (source_path, modded_path) = (vanilla.join(&relative), modded.join(&relative));
+        let (source_data, modded_data) = match (fs::read(&source_path), fs::read(&modded_path)) {
+            (Ok(source_data), Ok(modded_data)) => (source_data, modded_data),
+            (Err(e), _) => {
+                report.failed.push((source_path, e.into()));
+                continue;
+            },
+            (_, Err(e)) => {
+                report.failed.push((modded_path, e.into()));
+                continue;
+            }
+        };
+        // Most files of a modded tree are untouched copies, which don't need to be parsed to tell
+        if source_data == modded_data {
+            report.identical.push(relative);
+            continue;
+        }
+
+        let source = match prc::read_stream(&mut Cursor::new(source_data)) {
+            Ok(source) => source,
+            Err(e) => {
+                report.failed.push((source_path, e.into()));
+                continue;
+            }
+        };
+        let generated = match prc::read_stream(&mut Cursor::new(modded_data)) {
+            Ok(modded_params) => Diff::generate(&source, &modded_params),
+            Err(e) => {
+                report.failed.push((modded_path, e.into()));
+                continue;
+            }
+        };
+        let diff = generated.diff;
+        if diff.0.is_empty() {
+            // Files whose params only moved around, like fields in another order, have nothing to patch
+            if generated.skipped.is_empty() {
+                report.identical.push(relative);
+            } else {
+                report.unpatchable.push((relative, generated.skipped));
+            }
+            continue;
+        } else if !generated.skipped.is_empty() {
+            report.partial.push((relative.clone(), generated.skipped));
+        }
+
+        let patch = relative.with_extension(extension);
+        let path = output.join(&patch);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match labels {
+            Some(labels) => diff.save_labeled(&path, format, labels)?,
+            None => diff.save_as(&path, format)?
+        }
+        report.written.push((patch, diff.0.len()));
+    }
+    Ok(report)
+}
+
+// A directory under the system temp directory that is removed again when it is dropped
+#[cfg(test)]
+struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("prcx_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    fn write(&self, relative: &str, data: &[u8]) {
+        let path = self.0.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+fn tree_params(speed: f32) -> ParamStruct {
+    ParamStruct(vec![
+        (to_hash40("tree_speed"), ParamKind::Float(speed)),
+        (to_hash40("tree_count"), ParamKind::U8(1))
+    ])
+}
+
+#[test]
+fn apply_tree_test() {
+    let dir = TempDir::new("apply_tree");
+    let (vanilla, output) = (dir.0.join("vanilla"), dir.0.join("out"));
+    fs::create_dir_all(vanilla.join("fighter/param")).unwrap();
+    prc::save(vanilla.join("fighter/param/fighter_param.prc"), &tree_params(1.0)).unwrap();
+    prc::save(vanilla.join("fighter/param/untouched.prc"), &tree_params(1.0)).unwrap();
+
+    dir.write("mod_a/fighter/param/fighter_param.prctxt", b"tree_speed = 2.0\ntree_missing = 1\n");
+    dir.write("mod_b/fighter/param/fighter_param.prcx", b"tree_speed = 3.0\n");
+    dir.write("mod_b/fighter/param/no_vanilla.prcx", b"tree_speed = 3.0\n");
+    dir.write("mod_b/readme.txt", b"not a patch");
+
+    let report = apply_tree(&vanilla, &[dir.0.join("mod_a"), dir.0.join("mod_b")], &output).unwrap();
+    assert!(!report.is_complete());
+    assert_eq!(report.files.len(), 1);
+    let file = &report.files[0];
+    assert_eq!(file.path, Path::new("fighter/param/fighter_param.prc"));
+    assert_eq!((file.patches.len(), file.applied, file.skipped.len()), (2, 2, 1));
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, vanilla.join("fighter/param/no_vanilla.prc"));
+
+    assert_eq!(prc::open(output.join("fighter/param/fighter_param.prc")).unwrap(), tree_params(3.0));
+    assert!(!output.join("fighter/param/untouched.prc").exists());
+}
+
+#[test]
+fn diff_tree_test() {
+    let dir = TempDir::new("diff_tree");
+    let (vanilla, modded, output) = (dir.0.join("vanilla"), dir.0.join("modded"), dir.0.join("out"));
+    for (root, speed) in [(&vanilla, 1.0), (&modded, 2.0)].iter() {
+        fs::create_dir_all(root.join("fighter/param")).unwrap();
+        prc::save(root.join("fighter/param/fighter_param.prc"), &tree_params(*speed)).unwrap();
+        prc::save(root.join("fighter/param/same.prc"), &tree_params(1.0)).unwrap();
+    }
+    prc::save(vanilla.join("vanilla_only.prc"), &tree_params(1.0)).unwrap();
+    prc::save(modded.join("modded_only.prc"), &tree_params(1.0)).unwrap();
+    let mut grown = tree_params(1.0);
+    grown.0.push((to_hash40("tree_added"), ParamKind::U8(0)));
+    prc::save(modded.join("grown.prc"), &grown).unwrap();
+    prc::save(vanilla.join("grown.prc"), &tree_params(1.0)).unwrap();
+    // Changed values still get a patch when something else in the file can't go into it
+    let mut partial = tree_params(2.0);
+    partial.0.push((to_hash40("tree_added"), ParamKind::U8(0)));
+    prc::save(modded.join("partial.prc"), &partial).unwrap();
+    prc::save(vanilla.join("partial.prc"), &tree_params(1.0)).unwrap();
+    let mut nested = tree_params(1.0);
+    nested.0.push((to_hash40("tree_struct"), ParamKind::Struct(ParamStruct(vec![]))));
+    prc::save(vanilla.join("retyped.prc"), &nested).unwrap();
+    nested.0[2].1 = ParamKind::U8(0);
+    prc::save(modded.join("retyped.prc"), &nested).unwrap();
+
+    let report = diff_tree(&vanilla, &modded, &output, Format::Text).unwrap();
+    assert!(!report.is_complete());
+    assert_eq!(report.written, vec![(PathBuf::from("fighter/param/fighter_param.prctxt"), 1), (PathBuf::from("partial.prctxt"), 1)]);
+    assert_eq!(report.identical, vec![PathBuf::from("fighter/param/same.prc")]);
+    let reasons = |files: &[(PathBuf, Vec<Error>)]| -> Vec<(PathBuf, Vec<String>)> {
+        files.iter().map(|(path, skipped)| (path.clone(), skipped.iter().map(Error::to_string).collect())).collect()
+    };
+    assert_eq!(reasons(&report.partial), vec![(PathBuf::from("partial.prc"), vec![format!("`{:#x}` was added, which a patch can't hold.", to_hash40("tree_added").0)])]);
+    assert_eq!(reasons(&report.unpatchable), vec![
+        (PathBuf::from("grown.prc"), vec![format!("`{:#x}` was added, which a patch can't hold.", to_hash40("tree_added").0)]),
+        (PathBuf::from("retyped.prc"), vec![format!("Expected struct at `{:#x}` but found u8.", to_hash40("tree_struct").0)]),
+    ]);
+    assert_eq!(report.only_vanilla, vec![PathBuf::from("vanilla_only.prc")]);
+    assert_eq!(report.only_modded, vec![PathBuf::from("modded_only.prc")]);
+    assert!(report.failed.is_empty());
+
+    // Applying the patch tree to the vanilla tree gives back the modded files
+    let applied = dir.0.join("applied");
+    let report = apply_tree(&vanilla, &[&output], &applied).unwrap();
+    assert!(report.is_complete());
+    assert_eq!(prc::open(applied.join("fighter/param/fighter_param.prc")).unwrap(), tree_params(2.0));
+
+    // A file that can't be read fails rather than passing as identical
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(dir.0.join("missing.prc"), vanilla.join("broken.prc")).unwrap();
+        std::os::unix::fs::symlink(dir.0.join("missing.prc"), modded.join("broken.prc")).unwrap();
+        let report = diff_tree(&vanilla, &modded, dir.0.join("out_broken"), Format::Text).unwrap();
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, vanilla.join("broken.prc"));
+        assert!(!report.identical.contains(&PathBuf::from("broken.prc")));
+    }
+}